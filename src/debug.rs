@@ -1,13 +1,26 @@
 use bevy::prelude::*;
 
+#[cfg(feature = "debug_ai")]
+mod ai_overlay;
+
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
-    fn build(&self, _app: &mut App) {
+    fn build(&self, app: &mut App) {
         if cfg!(debug_assertions) {
             // app.add_plugin(LogDiagnosticsPlugin::default())
             // .add_plugin(FrameTimeDiagnosticsPlugin::default());
             // app.add_plugin(WorldInspectorPlugin::new()).register_inspectable::<EntityId>();
         }
+
+        add_ai_overlay(app);
     }
 }
+
+#[cfg(feature = "debug_ai")]
+fn add_ai_overlay(app: &mut App) {
+    app.add_plugin(ai_overlay::AiOverlayPlugin);
+}
+
+#[cfg(not(feature = "debug_ai"))]
+fn add_ai_overlay(_app: &mut App) {}
@@ -0,0 +1,225 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+    graphics::{AsciiSheet, TILE_SIZE},
+    world::{
+        components::{Alertness, EntityId, GameState, Order, Position},
+        dijkstra::{DijkstraMap, DijkstraMaps},
+        pathfinding::{self, Passability},
+        Game,
+    },
+    AppState,
+};
+
+/// The overlay goes over the tiles, the state of the monsters over them too.
+const DIJKSTRA_Z: f32 = 50.0;
+const PATH_Z: f32 = 60.0;
+const STATE_Z: f32 = 110.0;
+
+// CP437 characters of the ASCII sheet
+const FULL_BLOCK: char = '\u{db}';
+const MIDDLE_DOT: char = '\u{fa}';
+
+pub struct AiOverlayPlugin;
+
+impl Plugin for AiOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShownDijkstraMap>()
+            .add_system(cycle_dijkstra_map.run_in_state(AppState::InGame))
+            .add_system(draw_ai_overlay.run_in_state(AppState::InGame));
+    }
+}
+
+#[derive(Component)]
+struct AiOverlay;
+
+/// Dijkstra map drawn under the monsters, if any.
+#[derive(Default)]
+struct ShownDijkstraMap(Option<usize>);
+
+const DIJKSTRA_MAP_NAMES: [&str; 4] = ["approach", "flee", "unexplored", "items"];
+
+impl ShownDijkstraMap {
+    fn get<'a>(&self, dijkstra_maps: &'a DijkstraMaps) -> Option<&'a DijkstraMap> {
+        match self.0? {
+            0 => Some(&dijkstra_maps.approach),
+            1 => Some(&dijkstra_maps.flee),
+            2 => Some(&dijkstra_maps.unexplored),
+            3 => Some(&dijkstra_maps.items),
+            _ => None,
+        }
+    }
+}
+
+fn cycle_dijkstra_map(keyboard: Res<Input<KeyCode>>, mut shown: ResMut<ShownDijkstraMap>) {
+    if keyboard.just_pressed(KeyCode::F4) {
+        shown.0 = match shown.0 {
+            None => Some(0),
+            Some(index) if index + 1 < DIJKSTRA_MAP_NAMES.len() => Some(index + 1),
+            Some(_) => None,
+        };
+        match shown.0 {
+            Some(index) => println!("Showing the {} Dijkstra map", DIJKSTRA_MAP_NAMES[index]),
+            None => println!("Hiding the Dijkstra maps"),
+        }
+    }
+}
+
+/// Glyph and color telling what a monster is up to.
+fn ai_state(state: &GameState, entity_id: EntityId) -> (char, Color) {
+    if state.get_allegiance(entity_id).is_some() {
+        return ('A', Color::GREEN);
+    }
+    match state.get_morale(entity_id) {
+        Some(morale) if morale.cornered => return ('C', Color::ORANGE),
+        Some(morale) if morale.fleeing => return ('F', Color::WHITE),
+        _ => {}
+    }
+    match state
+        .get_awareness(entity_id)
+        .map(|awareness| awareness.alertness)
+    {
+        Some(Alertness::Sleeping) => ('z', Color::BLUE),
+        Some(Alertness::Alert) => ('?', Color::YELLOW),
+        Some(Alertness::Hunting) => ('!', Color::RED),
+        Some(Alertness::Idle) | None => ('.', Color::GRAY),
+    }
+}
+
+/// Where a monster is heading: its last idea of where the player is, or
+/// what its owner told it to.
+fn ai_goal(state: &GameState, entity_id: EntityId) -> Option<Position> {
+    match state.get_allegiance(entity_id) {
+        Some(allegiance) => match allegiance.order {
+            Order::Attack(target_id) => state.get_position(target_id).copied(),
+            Order::Guard(post) => Some(post),
+            Order::Follow => state.get_position(allegiance.owner).copied(),
+            Order::Stay => None,
+        },
+        None => state
+            .get_awareness(entity_id)
+            .and_then(|awareness| awareness.last_known_position),
+    }
+}
+
+fn spawn_overlay_glyph(
+    commands: &mut Commands,
+    ascii_sheet: &AsciiSheet,
+    character: char,
+    color: Color,
+    translation: Vec3,
+    size: f32,
+) {
+    let mut sprite = TextureAtlasSprite::new(character as usize);
+    sprite.custom_size = Some(Vec2::splat(size));
+    sprite.color = color;
+
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite,
+            texture_atlas: ascii_sheet.0.clone(),
+            transform: Transform {
+                translation,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(AiOverlay);
+}
+
+fn tile_translation(position: Position, z: f32) -> Vec3 {
+    Vec3::new(
+        position.x as f32 * TILE_SIZE,
+        position.y as f32 * TILE_SIZE,
+        z,
+    )
+}
+
+/// Redraws the state, goal and planned path of every monster, and the shown
+/// Dijkstra map, whenever the game changes.
+fn draw_ai_overlay(
+    mut commands: Commands,
+    world: Res<Game>,
+    dijkstra_maps: Res<DijkstraMaps>,
+    shown: Res<ShownDijkstraMap>,
+    ascii_sheet: Res<AsciiSheet>,
+    overlays: Query<Entity, With<AiOverlay>>,
+) {
+    if !world.is_changed() && !dijkstra_maps.is_changed() && !shown.is_changed() {
+        return;
+    }
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let state = &world.state;
+
+    if let (Some(dijkstra_map), Some(map)) = (shown.get(&dijkstra_maps), state.map()) {
+        let values: Vec<_> = map
+            .tiles()
+            .filter_map(|(position, _)| dijkstra_map.get(position).map(|value| (position, value)))
+            .collect();
+        let lowest = values.iter().map(|&(_, value)| value).min().unwrap_or(0);
+        let highest = values.iter().map(|&(_, value)| value).max().unwrap_or(0);
+        let range = (highest - lowest).max(1) as f32;
+
+        // Warm tiles are close to the goals, cold ones far from them
+        for (position, value) in values {
+            let heat = 1.0 - (value - lowest) as f32 / range;
+            spawn_overlay_glyph(
+                &mut commands,
+                &ascii_sheet,
+                FULL_BLOCK,
+                Color::rgba(heat, 0.2, 1.0 - heat, 0.35),
+                tile_translation(position, DIJKSTRA_Z),
+                TILE_SIZE,
+            );
+        }
+    }
+
+    for &entity_id in state.aibrain.keys() {
+        let position = match state.get_position(entity_id) {
+            Some(&position) => position,
+            None => continue,
+        };
+        let (character, color) = ai_state(state, entity_id);
+
+        // State in the top right corner of the monster
+        let corner = Vec3::new(TILE_SIZE * 0.35, TILE_SIZE * 0.35, 0.0);
+        spawn_overlay_glyph(
+            &mut commands,
+            &ascii_sheet,
+            character,
+            color,
+            tile_translation(position, STATE_Z) + corner,
+            TILE_SIZE * 0.5,
+        );
+
+        if let Some(goal) = ai_goal(state, entity_id) {
+            let passability = Passability::for_entity(entity_id);
+            if let Ok(path) =
+                pathfinding::find_path(state, &world.spatial_position, position, goal, &passability)
+            {
+                for &step in path.steps.iter() {
+                    spawn_overlay_glyph(
+                        &mut commands,
+                        &ascii_sheet,
+                        MIDDLE_DOT,
+                        color,
+                        tile_translation(step, PATH_Z),
+                        TILE_SIZE,
+                    );
+                }
+            }
+            spawn_overlay_glyph(
+                &mut commands,
+                &ascii_sheet,
+                'X',
+                color,
+                tile_translation(goal, PATH_Z),
+                TILE_SIZE,
+            );
+        }
+    }
+}
@@ -1,15 +1,24 @@
+use std::collections::{HashMap, HashSet};
+
 use bevy::{
     math::Vec3,
     prelude::{
-        App, Commands, Entity, EventReader, EventWriter, Plugin, Query, Res, ResMut, Transform,
+        Added, App, Commands, DespawnRecursiveExt, Entity, EventReader, EventWriter, Plugin, Query,
+        Res, ResMut, TextureAtlasSprite, Transform, Visibility, With,
     },
 };
 use iyes_loopless::prelude::IntoConditionalSystem;
 
 use crate::{
-    graphics::{spawn_ascii_sprite, AsciiSheet, TILE_SIZE},
+    graphics::{
+        dimmed, lit, spawn_ascii_sprite, spawn_tile_sprite, AsciiSheet, BaseColor, TileSprite,
+        TILE_SIZE,
+    },
     world::{
         components::{self, EntityId, Glyph, Name, Position},
+        levels::ChangeLevel,
+        lighting::LightMap,
+        map::{TileMap, TileType},
         Game, GameEvent,
     },
     AppState,
@@ -19,23 +28,40 @@ pub struct EventsPlugin;
 
 impl Plugin for EventsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<MoveSprite>()
+        app.init_resource::<PlayerView>()
+            .add_event::<MoveSprite>()
             .add_event::<SpawnSprite>()
             .add_event::<DeleteSprite>()
             .add_event::<EntityNearby>()
+            .add_event::<SpawnMap>()
+            .add_event::<ChangeTiles>()
+            .add_event::<ClearSprites>()
+            .add_event::<ChangeGlyph>()
             .add_system(map_events.run_in_state(AppState::InGame))
             .add_system(move_listener.run_in_state(AppState::InGame))
             .add_system(delete_listener.run_in_state(AppState::InGame))
-            .add_system(spawn_listener.run_in_state(AppState::InGame));
+            .add_system(spawn_listener.run_in_state(AppState::InGame))
+            .add_system(map_listener.run_in_state(AppState::InGame))
+            .add_system(tiles_listener.run_in_state(AppState::InGame))
+            .add_system(clear_listener.run_in_state(AppState::InGame))
+            .add_system(glyph_listener.run_in_state(AppState::InGame))
+            .add_system(light_sprites.run_in_state(AppState::InGame))
+            .add_system(fog_of_war.run_in_state(AppState::InGame));
     }
 }
 
 fn map_events(
     mut world: ResMut<Game>,
+    mut player_view: ResMut<PlayerView>,
     mut movement_events: EventWriter<MoveSprite>,
     mut spawn_events: EventWriter<SpawnSprite>,
     mut delete_events: EventWriter<DeleteSprite>,
     mut entity_nearby_events: EventWriter<EntityNearby>,
+    mut spawn_map_events: EventWriter<SpawnMap>,
+    mut change_tiles_events: EventWriter<ChangeTiles>,
+    mut change_level_events: EventWriter<ChangeLevel>,
+    mut clear_events: EventWriter<ClearSprites>,
+    mut glyph_events: EventWriter<ChangeGlyph>,
 ) {
     for event in world.events_queue.drain(..) {
         match event {
@@ -67,6 +93,24 @@ fn map_events(
                 name,
                 position,
             }),
+            GameEvent::MapChanged { map } => spawn_map_events.send(SpawnMap { map }),
+            GameEvent::TilesChanged { tiles } => change_tiles_events.send(ChangeTiles { tiles }),
+            GameEvent::GlyphChanged { entity_id, glyph } => {
+                glyph_events.send(ChangeGlyph { entity_id, glyph })
+            }
+            GameEvent::TookStairs { entity_id, stairs } => {
+                change_level_events.send(ChangeLevel { entity_id, stairs })
+            }
+            GameEvent::LevelChanged => clear_events.send(ClearSprites),
+            GameEvent::FieldOfView {
+                visible_tiles,
+                visible_entities,
+                light_map,
+            } => {
+                player_view.visible_tiles = visible_tiles;
+                player_view.visible_entities = visible_entities;
+                player_view.light_map = light_map;
+            }
         }
     }
 }
@@ -146,3 +190,135 @@ pub struct EntityNearby {
     pub name: Name,
     pub position: Position,
 }
+
+pub struct SpawnMap {
+    pub map: TileMap,
+}
+
+fn map_listener(
+    mut commands: Commands,
+    mut events: EventReader<SpawnMap>,
+    ascii_sheet: Res<AsciiSheet>,
+    tiles: Query<Entity, With<TileSprite>>,
+) {
+    for event in events.iter() {
+        for entity in tiles.iter() {
+            commands.entity(entity).despawn();
+        }
+        for (position, tile) in event.map.tiles() {
+            spawn_tile_sprite(
+                &mut commands,
+                &ascii_sheet,
+                tile.glyph(),
+                position,
+                event.map.is_revealed(position),
+            );
+        }
+    }
+}
+
+pub struct ChangeTiles {
+    pub tiles: Vec<(Position, TileType)>,
+}
+
+fn tiles_listener(
+    mut events: EventReader<ChangeTiles>,
+    mut tiles: Query<(&mut TileSprite, &mut TextureAtlasSprite)>,
+) {
+    for event in events.iter() {
+        let changed: HashMap<_, _> = event.tiles.iter().copied().collect();
+        for (mut tile, mut sprite) in tiles.iter_mut() {
+            if let Some(tile_type) = changed.get(&tile.position) {
+                tile.glyph = tile_type.glyph();
+                sprite.index = tile.glyph.character as usize;
+            }
+        }
+    }
+}
+
+pub struct ChangeGlyph {
+    pub entity_id: EntityId,
+    pub glyph: Glyph,
+}
+
+fn glyph_listener(
+    mut events: EventReader<ChangeGlyph>,
+    mut sprites: Query<(&mut TextureAtlasSprite, &mut BaseColor, &EntityId)>,
+) {
+    for event in events.iter() {
+        for (mut sprite, mut base_color, &id) in sprites.iter_mut() {
+            if id == event.entity_id {
+                sprite.index = event.glyph.character as usize;
+                base_color.0 = event.glyph.color;
+            }
+        }
+    }
+}
+
+pub struct ClearSprites;
+
+fn clear_listener(
+    mut commands: Commands,
+    mut events: EventReader<ClearSprites>,
+    sprites: Query<Entity, With<EntityId>>,
+) {
+    for _ in events.iter() {
+        for entity in sprites.iter() {
+            // The player sprite holds the camera
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// What the player saw after the last processed action.
+#[derive(Default)]
+pub struct PlayerView {
+    pub visible_tiles: HashSet<Position>,
+    pub visible_entities: HashSet<EntityId>,
+    pub light_map: LightMap,
+}
+
+// Checked every frame so that sprites spawned or moved after the view was
+// computed are hidden and lit too
+fn light_sprites(
+    player_view: Res<PlayerView>,
+    mut sprites: Query<(
+        &EntityId,
+        &BaseColor,
+        &Transform,
+        &mut TextureAtlasSprite,
+        &mut Visibility,
+    )>,
+) {
+    for (id, base_color, transform, mut sprite, mut visibility) in sprites.iter_mut() {
+        visibility.is_visible = player_view.visible_entities.contains(id);
+
+        let position = Position {
+            x: (transform.translation.x / TILE_SIZE).round() as i64,
+            y: (transform.translation.y / TILE_SIZE).round() as i64,
+        };
+        sprite.color = lit(base_color.0, player_view.light_map.get(position));
+    }
+}
+
+/// Tiles in view are tinted by the light they get, remembered ones are dimmed
+/// and the ones never seen stay hidden.
+fn fog_of_war(
+    player_view: Res<PlayerView>,
+    mut tiles: Query<(&mut TileSprite, &mut TextureAtlasSprite, &mut Visibility)>,
+    new_tiles: Query<Entity, Added<TileSprite>>,
+) {
+    if !player_view.is_changed() && new_tiles.iter().next().is_none() {
+        return;
+    }
+
+    for (mut tile, mut sprite, mut visibility) in tiles.iter_mut() {
+        if player_view.visible_tiles.contains(&tile.position) {
+            tile.revealed = true;
+            sprite.color = lit(tile.glyph.color, player_view.light_map.get(tile.position));
+        } else {
+            sprite.color = dimmed(tile.glyph.color);
+        }
+        visibility.is_visible = tile.revealed;
+    }
+}
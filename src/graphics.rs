@@ -3,7 +3,7 @@ use iyes_loopless::prelude::AppLooplessStateExt;
 
 use crate::{
     input::CameraLock,
-    world::components::{EntityId, Glyph, Player},
+    world::components::{EntityId, Glyph, Player, Position},
     AppState,
 };
 
@@ -14,6 +14,39 @@ pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
 pub const RESOLUTION: f32 = 16.0 / 9.0;
 pub const TILE_SIZE: f32 = 0.05;
 
+#[derive(Component)]
+pub struct TileSprite {
+    pub position: Position,
+    pub glyph: Glyph,
+    pub revealed: bool,
+}
+
+/// Color of an entity glyph before it is lit.
+#[derive(Component)]
+pub struct BaseColor(pub Color);
+
+/// Color of a remembered tile that is out of sight.
+pub fn dimmed(color: Color) -> Color {
+    Color::rgba(
+        color.r() * 0.35,
+        color.g() * 0.35,
+        color.b() * 0.35,
+        color.a(),
+    )
+}
+
+/// Color of a glyph under the given light. Anything in view keeps a bit of
+/// its color so that it can be made out in the dark.
+pub fn lit(color: Color, light: Color) -> Color {
+    let dimmest = dimmed(color);
+    Color::rgba(
+        (color.r() * light.r()).max(dimmest.r()),
+        (color.g() * light.g()).max(dimmest.g()),
+        (color.b() * light.b()).max(dimmest.b()),
+        color.a(),
+    )
+}
+
 impl Plugin for GraphicsPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ClearColor(CLEAR))
@@ -54,6 +87,7 @@ pub fn spawn_ascii_sprite(
 
     entity.insert(Name::new(name));
     entity.insert(id);
+    entity.insert(BaseColor(glyph.color));
 
     if is_player {
         entity.insert(Player);
@@ -64,6 +98,42 @@ pub fn spawn_ascii_sprite(
     entity_id
 }
 
+pub fn spawn_tile_sprite(
+    commands: &mut Commands,
+    ascii_sheet: &AsciiSheet,
+    glyph: Glyph,
+    position: Position,
+    revealed: bool,
+) -> Entity {
+    let mut sprite = TextureAtlasSprite::new(glyph.character as usize);
+    sprite.custom_size = Some(Vec2::splat(TILE_SIZE));
+    sprite.color = dimmed(glyph.color);
+
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite,
+            texture_atlas: ascii_sheet.0.clone(),
+            transform: Transform {
+                translation: Vec3::new(
+                    position.x as f32 * TILE_SIZE,
+                    position.y as f32 * TILE_SIZE,
+                    0.0,
+                ),
+                ..Default::default()
+            },
+            visibility: Visibility {
+                is_visible: revealed,
+            },
+            ..Default::default()
+        })
+        .insert(TileSprite {
+            position,
+            glyph,
+            revealed,
+        })
+        .id()
+}
+
 fn load_ascii_tileset(
     mut commands: Commands,
     assets: Res<AssetServer>,
@@ -2,20 +2,41 @@ use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 
 use crate::{
+    raw_loader::{GameData, GameDataHandle},
     save::SaveEvent,
     turn::NextAction,
-    world::{actions::ActionType, components::*},
+    world::{
+        actions::ActionType,
+        allies,
+        components::*,
+        dijkstra::DijkstraMaps,
+        fov,
+        utility::{self, UtilityTrace},
+        EntityIdGenerator, Game,
+    },
     AppState,
 };
 
+/// Template of the demon answering a summoning.
+const SUMMONED_TEMPLATE: &str = "imp";
+
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(movement_input.run_in_state(AppState::InGame))
             .add_system(grab_input.run_in_state(AppState::InGame))
+            .add_system(stairs_input.run_in_state(AppState::InGame))
+            .add_system(door_input.run_in_state(AppState::InGame))
+            .add_system(trap_input.run_in_state(AppState::InGame))
+            .add_system(dig_input.run_in_state(AppState::InGame))
+            .add_system(explore_input.run_in_state(AppState::InGame))
+            .add_system(summon_input.run_in_state(AppState::InGame))
+            .add_system(order_input.run_in_state(AppState::InGame))
+            .add_system(drink_input.run_in_state(AppState::InGame))
             .add_system(toggle_camera_lock.run_in_state(AppState::InGame))
-            .add_system(debug_save.run_in_state(AppState::InGame));
+            .add_system(debug_save.run_in_state(AppState::InGame))
+            .add_system(debug_utility_trace.run_in_state(AppState::InGame));
     }
 }
 
@@ -47,6 +68,11 @@ fn movement_input(
     mut next_action: ResMut<NextAction>,
     players: Query<&EntityId, With<Player>>,
 ) {
+    // Directions are used to dig while shift is held
+    if keyboard.pressed(KeyCode::LShift) {
+        return;
+    }
+
     for &entity_id in players.iter() {
         if keyboard.just_pressed(KeyCode::W) {
             next_action.push(ActionType::MoveBy {
@@ -104,9 +130,239 @@ fn grab_input(
     }
 }
 
+fn stairs_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    for &entity_id in players.iter() {
+        if keyboard.just_pressed(KeyCode::Period) {
+            next_action.push(ActionType::Descend {
+                entity_id,
+                cost: 100,
+            });
+        }
+        if keyboard.just_pressed(KeyCode::Comma) {
+            next_action.push(ActionType::Ascend {
+                entity_id,
+                cost: 100,
+            });
+        }
+    }
+}
+
+fn door_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    for &entity_id in players.iter() {
+        if keyboard.just_pressed(KeyCode::C) {
+            next_action.push(ActionType::CloseDoor {
+                entity_id,
+                cost: 100,
+            });
+        }
+        if keyboard.just_pressed(KeyCode::L) {
+            next_action.push(ActionType::LockDoor {
+                entity_id,
+                cost: 100,
+            });
+        }
+        if keyboard.just_pressed(KeyCode::U) {
+            next_action.push(ActionType::UnlockDoor {
+                entity_id,
+                cost: 100,
+            });
+        }
+    }
+}
+
+fn trap_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    for &entity_id in players.iter() {
+        if keyboard.just_pressed(KeyCode::F) {
+            next_action.push(ActionType::Search {
+                entity_id,
+                cost: 100,
+            });
+        }
+        if keyboard.just_pressed(KeyCode::T) {
+            next_action.push(ActionType::Disarm {
+                entity_id,
+                cost: 100,
+            });
+        }
+    }
+}
+
+fn dig_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    if !keyboard.pressed(KeyCode::LShift) {
+        return;
+    }
+
+    for &entity_id in players.iter() {
+        for (key, dx, dy) in [
+            (KeyCode::W, 0, 1),
+            (KeyCode::S, 0, -1),
+            (KeyCode::A, -1, 0),
+            (KeyCode::D, 1, 0),
+        ] {
+            if keyboard.just_pressed(key) {
+                next_action.push(ActionType::Dig {
+                    entity_id,
+                    dx,
+                    dy,
+                    cost: 200,
+                });
+            }
+        }
+    }
+}
+
+/// Takes one step toward the closest unexplored tile.
+fn explore_input(
+    keyboard: Res<Input<KeyCode>>,
+    world: Res<Game>,
+    mut dijkstra_maps: ResMut<DijkstraMaps>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::X) {
+        return;
+    }
+
+    dijkstra_maps.refresh(&world.state, &world.spatial_position);
+    for &entity_id in players.iter() {
+        if let Some(&position) = world.state.get_position(entity_id) {
+            match dijkstra_maps.unexplored.downhill(position) {
+                Some((dx, dy)) => next_action.push(ActionType::MoveBy {
+                    entity_id,
+                    dx,
+                    dy,
+                    cost: 100,
+                }),
+                None => println!("Nothing left to explore"),
+            }
+        }
+    }
+}
+
+fn summon_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut next_action: ResMut<NextAction>,
+    mut id_generator: ResMut<EntityIdGenerator>,
+    data_asset: Res<Assets<GameData>>,
+    game_data_handle: Res<GameDataHandle>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Z) {
+        return;
+    }
+
+    let template = data_asset
+        .get(&game_data_handle.0)
+        .and_then(|game_data| game_data.entities.get(SUMMONED_TEMPLATE));
+    for &entity_id in players.iter() {
+        if let Some(template) = template {
+            next_action.push(ActionType::Summon {
+                entity_id,
+                summoned_id: id_generator.next(),
+                template: template.clone(),
+                cost: 300,
+            });
+        }
+    }
+}
+
+/// Recruits followers and gives orders to every ally.
+fn order_input(
+    keyboard: Res<Input<KeyCode>>,
+    world: Res<Game>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    for &entity_id in players.iter() {
+        if keyboard.just_pressed(KeyCode::V) {
+            next_action.push(ActionType::Recruit {
+                entity_id,
+                cost: 100,
+            });
+        }
+
+        let order = if keyboard.just_pressed(KeyCode::Key1) {
+            Some(Order::Follow)
+        } else if keyboard.just_pressed(KeyCode::Key2) {
+            Some(Order::Stay)
+        } else if keyboard.just_pressed(KeyCode::Key3) {
+            let target = allies::nearest_enemy(
+                &world.state,
+                &world.spatial_position,
+                entity_id,
+                fov::VIEW_RADIUS,
+            );
+            if target.is_none() {
+                println!("There is no enemy in sight");
+            }
+            target.map(|(target_id, _)| Order::Attack(target_id))
+        } else if keyboard.just_pressed(KeyCode::Key4) {
+            world
+                .state
+                .get_position(entity_id)
+                .map(|&post| Order::Guard(post))
+        } else {
+            None
+        };
+
+        if let Some(order) = order {
+            next_action.push(ActionType::GiveOrder {
+                entity_id,
+                order,
+                cost: 0,
+            });
+        }
+    }
+}
+
+fn drink_input(
+    keyboard: Res<Input<KeyCode>>,
+    world: Res<Game>,
+    mut next_action: ResMut<NextAction>,
+    players: Query<&EntityId, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    for &entity_id in players.iter() {
+        match utility::carried_potion(&world.state, entity_id) {
+            Some(item_id) => next_action.push(ActionType::Drink {
+                entity_id,
+                item_id,
+                cost: 100,
+            }),
+            None => println!("There is nothing to drink"),
+        }
+    }
+}
+
 // DEBUG ////////////////////////////////////////////////////////////////
 fn debug_save(keyboard: Res<Input<KeyCode>>, mut save_event: EventWriter<SaveEvent>) {
     if keyboard.just_pressed(KeyCode::R) {
         save_event.send(SaveEvent);
     }
 }
+
+fn debug_utility_trace(keyboard: Res<Input<KeyCode>>, mut utility_trace: ResMut<UtilityTrace>) {
+    if keyboard.just_pressed(KeyCode::F2) {
+        utility_trace.0 = !utility_trace.0;
+        println!("Utility trace: {}", utility_trace.0);
+    }
+}
@@ -1,6 +1,9 @@
 use bevy::{
     asset::{AssetLoader, BoxedFuture, LoadContext, LoadState, LoadedAsset},
-    prelude::{App, AssetServer, Color, Commands, Handle, HandleUntyped, Plugin, Res, AddAsset},
+    prelude::{
+        AddAsset, App, AssetServer, Assets, Color, Commands, Handle, HandleUntyped, Plugin, Res,
+        ResMut,
+    },
     reflect::TypeUuid,
     utils::HashMap,
 };
@@ -11,7 +14,10 @@ use iyes_loopless::{
     state::NextState,
 };
 
-use crate::{world::components::*, AppState};
+use crate::{
+    world::{components::*, map::TileType, stats::Stats},
+    AppState,
+};
 
 pub struct RawLoaderPlugin;
 
@@ -24,17 +30,31 @@ impl Plugin for RawLoaderPlugin {
     }
 }
 
+const RAW_FILES: [&str; 7] = [
+    "monsters.raw",
+    "items.raw",
+    "prefabs.raw",
+    "traps.raw",
+    "spawns.raw",
+    "factions.raw",
+    "utility.raw",
+];
+
 struct AssetsLoading(Vec<HandleUntyped>);
+struct RawFiles(Vec<Handle<GameData>>);
 pub struct GameDataHandle(pub Handle<GameData>);
 
 fn load_game_data(mut commands: Commands, asset_server: Res<AssetServer>) {
     let mut loading = AssetsLoading(vec![]);
-    let monsters: Handle<GameData> = asset_server.load("monsters.raw");
+    let mut raw_files = RawFiles(vec![]);
 
-    loading.0.push(monsters.clone_untyped());
+    for path in RAW_FILES {
+        let raw_file: Handle<GameData> = asset_server.load(path);
+        loading.0.push(raw_file.clone_untyped());
+        raw_files.0.push(raw_file);
+    }
 
-    let game_data_handle = GameDataHandle(monsters);
-    commands.insert_resource(game_data_handle);
+    commands.insert_resource(raw_files);
     commands.insert_resource(loading);
 }
 
@@ -42,14 +62,27 @@ fn check_assets_ready(
     mut commands: Commands,
     server: Res<AssetServer>,
     loading: Res<AssetsLoading>,
+    raw_files: Res<RawFiles>,
+    mut game_data: ResMut<Assets<GameData>>,
 ) {
     match server.get_group_load_state(loading.0.iter().map(|h| h.id)) {
         LoadState::Failed => {
             // one of our assets had an error
         }
         LoadState::Loaded => {
+            // Every raw file is merged into a single GameData
+            let mut merged = GameData::default();
+            for handle in raw_files.0.iter() {
+                if let Some(data) = game_data.get(handle) {
+                    merged.merge(data);
+                }
+            }
+            merged.resolve_utility_profiles();
+            commands.insert_resource(GameDataHandle(game_data.add(merged)));
+
             commands.insert_resource(NextState(AppState::MainMenu));
             commands.remove_resource::<AssetsLoading>();
+            commands.remove_resource::<RawFiles>();
         }
         _ => {
             // NotLoaded/Loading: not fully ready yet
@@ -68,9 +101,9 @@ impl AssetLoader for RawAssetLoader {
     ) -> BoxedFuture<'a, Result<()>> {
         Box::pin(async move {
             let source = std::str::from_utf8(bytes)?;
-            let entities = raw_loader::entities(source)?;
+            let game_data = raw_loader::game_data(source)?;
 
-            load_context.set_default_asset(LoadedAsset::new(GameData { entities }));
+            load_context.set_default_asset(LoadedAsset::new(game_data));
             Ok(())
         })
     }
@@ -82,31 +115,115 @@ impl AssetLoader for RawAssetLoader {
 
 peg::parser!(
     grammar raw_loader() for str {
-        pub rule entities() -> Entities = templates:(template() / comment())* {
-            templates.into_iter().flatten().fold(Entities::new(), |mut entities: Entities, (id, template)| {
-                entities.insert(id, template);
-                entities
+        pub rule game_data() -> GameData = entries:(prefab() / item() / trap() / spawn() / relation() / utility() / template() / comment() / blank_line())* {
+            entries.into_iter().flatten().fold(GameData::default(), |mut game_data: GameData, entry| {
+                match entry {
+                    Entry::Entity(id, template) => { game_data.entities.insert(id, template); }
+                    Entry::Item(id, template) => { game_data.items.insert(id, template); }
+                    Entry::Prefab(id, prefab) => { game_data.prefabs.insert(id, prefab); }
+                    Entry::Trap(id, template) => { game_data.traps.insert(id, template); }
+                    Entry::Spawn(spawn) => { game_data.spawns.push(spawn); }
+                    Entry::Relation(faction, other, relation) => { game_data.relations.push((faction, other, relation)); }
+                    Entry::Utility(profile, candidate) => { game_data.utilities.entry(profile).or_default().push(candidate); }
+                }
+                game_data
             })
         }
 
-        rule template() -> Option<(String, EntityTemplate)>
-        = id:(word()) _ name:(word()) _ glyph:(glyph()) _ attack:(attack()) _ health:(health()) _ initiative:(initiative()) end() {
-            Some((id, EntityTemplate { name: Name(name), glyph, attack, health, initiative }))
+        rule template() -> Option<Entry>
+        = id:(word()) _ name:(name()) _ glyph:(glyph()) _ attack:(attack()) _ health:(health()) _ initiative:(initiative()) stats:(_ st:stats() { st })? ai:(_ a:ai() { a })? faction:(_ f:faction() { f })? perception:(_ p:senses() { p })? morale:(_ m:morale() { m })? spell:(_ s:spell() { s })? utility:(_ u:utility_profile() { u })? light:(_ l:light() { l })? end() {?
+            // Health comes either from the raw or from the stats, never both
+            match (health, stats) {
+                (Some(_), Some(_)) => Err("a health of - for a template with stats"),
+                (None, None) => Err("a health for a template without stats"),
+                _ => Ok(Some(Entry::Entity(id, EntityTemplate { name, glyph, attack, health, initiative, stats, ai, faction, perception, morale, spell, utility, light }))),
+            }
+        }
+        rule item() -> Option<Entry>
+        = "item" _ id:(word()) _ name:(name()) _ glyph:(glyph()) healing:(_ h:heals() { h })? light:(_ l:light() { l })? end() {
+            Some(Entry::Item(id, ItemTemplate { name, glyph, healing, light }))
+        }
+        rule trap() -> Option<Entry>
+        = "trap" _ id:(word()) _ name:(name()) _ glyph:(glyph()) _ kind:(trap_kind()) _ damage:(i64()) _ detection:(u32()) _ disarm:(u32()) end() {
+            Some(Entry::Trap(id, TrapTemplate { name, glyph, trap: Trap { kind, damage, detection, disarm } }))
+        }
+        rule trap_kind() -> TrapKind
+        = "pit" { TrapKind::Pit } / "dart" { TrapKind::Dart } / "teleport" { TrapKind::Teleport } / "alarm" { TrapKind::Alarm }
+        rule spawn() -> Option<Entry>
+        = "spawn" _ kind:(spawn_kind()) _ template_id:(word()) _ min_depth:(u32()) "-" max_depth:(u32()) _ weight:(u32()) _ depth_bonus:(u32()) _ group_min:(u32()) "-" group_max:(u32()) _ rarity:(rarity()) end() {
+            Some(Entry::Spawn(SpawnEntry { kind, template_id, min_depth, max_depth, weight, depth_bonus, group_min, group_max, rarity }))
+        }
+        rule spawn_kind() -> SpawnKind
+        = "monster" { SpawnKind::Monster } / "item" { SpawnKind::Item } / "trap" { SpawnKind::Trap }
+        rule rarity() -> Rarity
+        = "common" { Rarity::Common } / "uncommon" { Rarity::Uncommon } / "rare" { Rarity::Rare }
+        rule prefab() -> Option<Entry>
+        = "prefab" _ id:(word()) end() legend:(legend()*) rows:(prefab_row()+) "end" end() {?
+            PrefabTemplate::new(legend, rows).map(|prefab| Some(Entry::Prefab(id, prefab)))
         }
-        rule comment() -> Option<(String, EntityTemplate)> = "#" skip_to_line_end() { None }
+        rule legend() -> (char, String)
+        = "legend" _ symbol:([^ ' ' | '\n' | '\r']) _ template_id:(word()) end() { (symbol, template_id) }
+        rule prefab_row() -> String = !("end" end()) row:$([^ '\n' | '\r']+) end() { row.to_owned() }
+
+        rule comment() -> Option<Entry> = "#" skip_to_line_end() { None }
+        rule blank_line() -> Option<Entry> = [' ']* ("\n" / "\r\n") { None }
 
         rule attack() -> Attack = attack:(i64()) { Attack(attack) }
-        rule health() -> Health = health:(i64()) { Health(health) }
+        rule health() -> Option<Health> = health:(i64()) { Some(Health(health)) } / "-" { None }
         rule initiative() -> Initiative = initiative:(u32()) { Initiative(initiative) }
+        rule ai() -> AiBrain
+        = "ai" _ brain:(
+            "chaser" { AiBrain::MeleeChaser } / "kiter" { AiBrain::RangedKiter } / "coward" { AiBrain::Coward }
+            / "stationary" { AiBrain::Stationary } / "wanderer" { AiBrain::Wanderer }
+        ) { brain }
+        rule faction() -> Faction = "faction" _ faction:(word()) { Faction(faction) }
+        rule stats() -> Stats = "stats" _ might:(i64()) _ agility:(i64()) _ vitality:(i64()) _ will:(i64()) {
+            Stats { might: Might(might), agility: Agility(agility), vitality: Vitality(vitality), will: Will(will) }
+        }
+        rule morale() -> Morale = "morale" _ flee_threshold:(i64()) _ courage:(i64()) { Morale::new(flee_threshold, courage) }
+        rule spell() -> Spell = "spell" _ damage:(i64()) _ range:(i64()) { Spell { damage, range } }
+        rule heals() -> Healing = "heals" _ amount:(i64()) { Healing(amount) }
+        rule utility_profile() -> UtilityAi
+        = "utility" _ profile:(word()) { UtilityAi { profile, candidates: vec![] } }
+        rule utility() -> Option<Entry>
+        = "utility" _ profile:(word()) _ candidate:(candidate()) _ weight:(f32()) considerations:(_ c:consideration() { c })* end() {
+            Some(Entry::Utility(profile, UtilityCandidate { candidate, weight, considerations }))
+        }
+        rule candidate() -> Candidate
+        = "attack" { Candidate::Attack } / "move_toward" { Candidate::MoveToward } / "flee" { Candidate::Flee }
+        / "cast" { Candidate::Cast } / "drink_potion" { Candidate::DrinkPotion } / "pick_up" { Candidate::PickUp }
+        / "wander" { Candidate::Wander } / "wait" { Candidate::Wait }
+        rule consideration() -> Consideration = input:(consideration_input()) _ curve:(curve()) { Consideration { input, curve } }
+        rule consideration_input() -> ConsiderationInput
+        = "health" { ConsiderationInput::Health } / "distance" { ConsiderationInput::Distance }
+        / "adjacent" { ConsiderationInput::Adjacent } / "visible" { ConsiderationInput::Visible }
+        / "in_range" { ConsiderationInput::InRange } / "potion" { ConsiderationInput::Potion }
+        / "item_here" { ConsiderationInput::ItemHere }
+        rule curve() -> Curve
+        = "linear" _ slope:(f32()) _ offset:(f32()) { Curve::Linear { slope, offset } }
+        / "above" _ threshold:(f32()) { Curve::Above(threshold) }
+        / "below" _ threshold:(f32()) { Curve::Below(threshold) }
+        rule senses() -> Perception = "senses" _ sight:(i64()) _ hearing:(i64()) { Perception { sight, hearing } }
+        rule relation() -> Option<Entry>
+        = "relation" _ faction:(word()) _ other:(word()) _ relation:(
+            "allied" { Relation::Allied } / "neutral" { Relation::Neutral } / "hostile" { Relation::Hostile }
+        ) end() {
+            Some(Entry::Relation(Faction(faction), Faction(other), relation))
+        }
+        rule light() -> LightSource = "light" _ radius:(i64()) _ color:(color()) _ intensity:(u32()) {
+            LightSource { radius, color, intensity: intensity as f32 / 100.0 }
+        }
 
         rule glyph() -> Glyph = character:([_]) _ color:(color()) { Glyph { character, color } }
         rule color() -> Color = "#" color:$(hex()*<6>) {? Color::hex(color).or(Err("Color error")) }
 
+        rule name() -> Name = name:(word()) { Name(name.replace('_', " ")) }
         rule word() -> String = word:$(character()+) { word.to_owned() }
         rule character() -> char = character:(['a'..='z' | 'A'..='Z' | '_']) { character }
 
         rule i64() -> i64 = digits:$(digit()+) {? digits.parse::<i64>().or(Err("Digits error")) }
         rule u32() -> u32 = digits:$(digit()+) {? digits.parse::<u32>().or(Err("Digits error")) }
+        rule f32() -> f32 = digits:$("-"? digit()+ ("." digit()+)?) {? digits.parse::<f32>().or(Err("Float error")) }
 
         rule hex() -> char = hex:(['0'..='9' | 'A'..='F']) { hex }
         rule digit() -> char = digit:(['0'..='9']) { digit }
@@ -120,11 +237,62 @@ peg::parser!(
 );
 
 type Entities = HashMap<String, EntityTemplate>;
+type Items = HashMap<String, ItemTemplate>;
+type Prefabs = HashMap<String, PrefabTemplate>;
+type Traps = HashMap<String, TrapTemplate>;
+
+enum Entry {
+    Entity(String, EntityTemplate),
+    Item(String, ItemTemplate),
+    Prefab(String, PrefabTemplate),
+    Trap(String, TrapTemplate),
+    Spawn(SpawnEntry),
+    Relation(Faction, Faction, Relation),
+    Utility(String, UtilityCandidate),
+}
 
-#[derive(Debug, TypeUuid)]
+#[derive(Debug, Default, TypeUuid)]
 #[uuid = "39cadc56-aa9c-4543-8640-a018b74b5052"]
 pub struct GameData {
     pub entities: Entities,
+    pub items: Items,
+    pub prefabs: Prefabs,
+    pub traps: Traps,
+    /// Spawn tables of every kind, in the order of the raw files.
+    pub spawns: Vec<SpawnEntry>,
+    pub relations: Vec<(Faction, Faction, Relation)>,
+    /// Candidate actions of every utility profile.
+    pub utilities: HashMap<String, Vec<UtilityCandidate>>,
+}
+
+impl GameData {
+    fn merge(&mut self, other: &GameData) {
+        self.entities.extend(other.entities.clone());
+        self.items.extend(other.items.clone());
+        self.prefabs.extend(other.prefabs.clone());
+        self.traps.extend(other.traps.clone());
+        self.spawns.extend(other.spawns.iter().cloned());
+        self.relations.extend(other.relations.iter().cloned());
+        for (profile, candidates) in other.utilities.iter() {
+            self.utilities
+                .entry(profile.clone())
+                .or_default()
+                .extend(candidates.iter().cloned());
+        }
+    }
+
+    /// Gives the monster templates the candidates of their utility profile,
+    /// once every raw file is merged.
+    fn resolve_utility_profiles(&mut self) {
+        for template in self.entities.values_mut() {
+            if let Some(utility) = template.utility.as_mut() {
+                match self.utilities.get(&utility.profile) {
+                    Some(candidates) => utility.candidates = candidates.clone(),
+                    None => println!("Unknown utility profile {}", utility.profile),
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -132,6 +300,134 @@ pub struct EntityTemplate {
     pub name: Name,
     pub glyph: Glyph,
     pub attack: Attack,
-    pub health: Health,
+    /// `None` when the health is given by the stats.
+    pub health: Option<Health>,
     pub initiative: Initiative,
+    pub stats: Option<Stats>,
+    pub ai: Option<AiBrain>,
+    pub faction: Option<Faction>,
+    pub perception: Option<Perception>,
+    pub morale: Option<Morale>,
+    pub spell: Option<Spell>,
+    pub utility: Option<UtilityAi>,
+    pub light: Option<LightSource>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ItemTemplate {
+    pub name: Name,
+    pub glyph: Glyph,
+    pub healing: Option<Healing>,
+    pub light: Option<LightSource>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrapTemplate {
+    pub name: Name,
+    pub glyph: Glyph,
+    pub trap: Trap,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SpawnKind {
+    Monster,
+    Item,
+    Trap,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+}
+
+impl Rarity {
+    /// Percentage applied to the weight of a spawn entry.
+    pub fn percent(self) -> u32 {
+        match self {
+            Rarity::Common => 100,
+            Rarity::Uncommon => 40,
+            Rarity::Rare => 10,
+        }
+    }
+}
+
+/// Line of a spawn table, telling how often a template shows up by depth.
+#[derive(Debug, Clone)]
+pub struct SpawnEntry {
+    pub kind: SpawnKind,
+    pub template_id: String,
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub weight: u32,
+    /// Added to the weight for every level below `min_depth`.
+    pub depth_bonus: u32,
+    pub group_min: u32,
+    pub group_max: u32,
+    pub rarity: Rarity,
+}
+
+impl SpawnEntry {
+    /// Weight of the entry on a level, 0 when out of its depth range.
+    pub fn weight_at(&self, depth: u32) -> u32 {
+        if depth < self.min_depth || depth > self.max_depth {
+            return 0;
+        }
+        (self.weight + self.depth_bonus * (depth - self.min_depth)) * self.rarity.percent() / 100
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefabCell {
+    Tile(TileType),
+    Door,
+    /// Floor with the monster or item of the given template id on it.
+    Spawn(String),
+}
+
+/// Hand drawn room, `cells` are stored row by row starting from the top.
+#[derive(Debug, Clone)]
+pub struct PrefabTemplate {
+    pub width: i64,
+    pub height: i64,
+    pub cells: Vec<PrefabCell>,
+}
+
+impl PrefabTemplate {
+    fn new(legend: Vec<(char, String)>, rows: Vec<String>) -> Result<Self, &'static str> {
+        let width = rows.first().map(|row| row.chars().count()).unwrap_or(0);
+        if rows.iter().any(|row| row.chars().count() != width) {
+            return Err("Prefab rows must have the same width");
+        }
+
+        let legend: HashMap<char, String> = legend.into_iter().collect();
+        let cells = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|symbol| match symbol {
+                '#' => Ok(PrefabCell::Tile(TileType::Wall)),
+                '.' => Ok(PrefabCell::Tile(TileType::Floor)),
+                ':' => Ok(PrefabCell::Tile(TileType::Rubble)),
+                '~' => Ok(PrefabCell::Tile(TileType::ShallowWater)),
+                '=' => Ok(PrefabCell::Tile(TileType::DeepWater)),
+                '+' => Ok(PrefabCell::Door),
+                symbol => legend
+                    .get(&symbol)
+                    .map(|template_id| PrefabCell::Spawn(template_id.clone()))
+                    .ok_or("Unknown prefab symbol"),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PrefabTemplate {
+            width: width as i64,
+            height: rows.len() as i64,
+            cells,
+        })
+    }
+
+    /// `x` goes right and `y` goes down, as drawn in the raw file.
+    pub fn cell(&self, x: i64, y: i64) -> &PrefabCell {
+        &self.cells[(y * self.width + x) as usize]
+    }
 }
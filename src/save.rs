@@ -2,13 +2,15 @@ use anyhow::{anyhow, Result};
 use bevy::prelude::*;
 use directories::UserDirs;
 use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::Write,
     path::PathBuf,
 };
 
-use crate::world::{components::GameState, Game};
+use crate::world::{components::GameState, levels::LevelStore, EntityIdGenerator, Game, GameSeed};
 
 pub struct SavePlugin;
 
@@ -46,17 +48,49 @@ fn get_saved_games_folder() -> Result<PathBuf> {
     Ok(saves)
 }
 
+/// Borrowed twin of `SaveData`, avoids cloning every level to save them.
+#[derive(Serialize)]
+struct SaveDataRef<'a> {
+    seed: u64,
+    next_entity_id: u64,
+    current_depth: u32,
+    current_level: &'a GameState,
+    levels: &'a HashMap<u32, GameState>,
+}
+
+#[derive(Deserialize)]
+pub struct SaveData {
+    pub seed: u64,
+    pub next_entity_id: u64,
+    pub current_depth: u32,
+    pub current_level: GameState,
+    pub levels: HashMap<u32, GameState>,
+}
+
 pub struct SaveEvent;
-fn save_world(world: Res<Game>, save_path: Res<SavePath>) {
-    let encoded = bincode::serialize(&world.state).unwrap();
+fn save_world(
+    world: Res<Game>,
+    levels: Res<LevelStore>,
+    seed: Res<GameSeed>,
+    id_generator: Res<EntityIdGenerator>,
+    save_path: Res<SavePath>,
+) {
+    let save_data = SaveDataRef {
+        seed: seed.0,
+        next_entity_id: id_generator.next_id(),
+        current_depth: levels.current_depth,
+        current_level: &world.state,
+        levels: &levels.levels,
+    };
+    let encoded = bincode::serialize(&save_data).unwrap();
     File::create(&save_path.0)
         .expect("Failed to create save file")
         .write_all(&encoded)
         .expect("Failed to write to save file");
 }
 
-pub fn load_saved_game(save_path: SavePath) -> Result<GameState> {
+pub fn load_saved_game(save_path: SavePath) -> Result<SaveData> {
     let bytes = fs::read(&save_path.0)?;
-    let decoded: GameState = bincode::deserialize(&bytes)?;
+    let decoded: SaveData = bincode::deserialize(&bytes)?;
     Ok(decoded)
 }
@@ -1,14 +1,33 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
-use rand::prelude::*;
 
 use crate::{
-    world::{actions::ActionType, components::PositionTreeObject, Game},
+    world::{
+        actions::ActionType,
+        ai,
+        components::{EntityId, Position, PositionTreeObject},
+        dijkstra::DijkstraMaps,
+        utility::UtilityTrace,
+        Game,
+    },
     AppState,
 };
 
+const ENERGY_PER_TICK: i64 = 50;
+/// Entities this close to the player run their AI on every energy tick.
+const FULL_SIMULATION_RADIUS: i64 = 20;
+/// Entities up to this distance only get coarse updates, farther ones are
+/// frozen until the player comes back.
+const COARSE_SIMULATION_RADIUS: i64 = 40;
+/// Entities with coarse updates act once every few ticks, with the energy of
+/// all of them, and only take the cheap steps of `ai::coarse_actions`.
+const COARSE_TICK_INTERVAL: u64 = 4;
+/// Most ticks a frozen entity catches up on when it comes back in range, so
+/// that it does not keep acting for ages after a long absence.
+const MAX_CATCH_UP_TICKS: u64 = 40;
+
 pub struct TurnPlugin;
 
 impl Plugin for TurnPlugin {
@@ -18,6 +37,60 @@ impl Plugin for TurnPlugin {
     }
 }
 
+/// How closely an entity is simulated, depending on its distance to the
+/// player. Entities on other levels are never simulated, only the current
+/// level lives in the `Game`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Simulation {
+    Full,
+    Coarse,
+    Frozen,
+}
+
+impl Simulation {
+    /// Ticks between two updates of an entity simulated this way.
+    fn interval(self) -> u64 {
+        match self {
+            Simulation::Coarse => COARSE_TICK_INTERVAL,
+            _ => 1,
+        }
+    }
+
+    fn at(player_position: Position, position: Position) -> Self {
+        let dx = position.x - player_position.x;
+        let dy = position.y - player_position.y;
+        let distance_2 = dx * dx + dy * dy;
+
+        if distance_2 <= FULL_SIMULATION_RADIUS * FULL_SIMULATION_RADIUS {
+            Simulation::Full
+        } else if distance_2 <= COARSE_SIMULATION_RADIUS * COARSE_SIMULATION_RADIUS {
+            Simulation::Coarse
+        } else {
+            Simulation::Frozen
+        }
+    }
+}
+
+/// Number of energy ticks since the start of the session.
+pub struct SimulationClock(u64);
+
+/// Tick at which each entity last got its energy. Entities that were coarse
+/// or frozen in between get the energy of every tick they missed.
+#[derive(Default)]
+pub struct LastSimulated(HashMap<EntityId, u64>);
+
+impl LastSimulated {
+    /// Ticks of energy owed to an entity at `tick`, and records that it got
+    /// them.
+    fn catch_up(&mut self, entity_id: EntityId, simulation: Simulation, tick: u64) -> u64 {
+        let missed = self
+            .0
+            .insert(entity_id, tick)
+            .map_or(simulation.interval(), |last| tick - last);
+        missed.min(MAX_CATCH_UP_TICKS)
+    }
+}
+
 pub struct NextAction(VecDeque<ActionType>);
 
 impl NextAction {
@@ -36,9 +109,18 @@ impl NextAction {
 
 fn setup(mut commands: Commands) {
     commands.insert_resource(NextAction::new());
+    commands.insert_resource(SimulationClock(0));
+    commands.insert_resource(LastSimulated::default());
 }
 
-fn turn_order(mut next_action: ResMut<NextAction>, mut world: ResMut<Game>) {
+fn turn_order(
+    mut next_action: ResMut<NextAction>,
+    mut world: ResMut<Game>,
+    mut clock: ResMut<SimulationClock>,
+    mut last_simulated: ResMut<LastSimulated>,
+    mut dijkstra_maps: ResMut<DijkstraMaps>,
+    utility_trace: Res<UtilityTrace>,
+) {
     let mut rng = rand::thread_rng();
 
     if let Some(&player_id) = world.state.player.keys().next() {
@@ -58,43 +140,73 @@ fn turn_order(mut next_action: ResMut<NextAction>, mut world: ResMut<Game>) {
             }
 
             if should_process_entities {
-                for energy in world.state.energy.values_mut() {
-                    energy.0 += 50;
-                }
+                clock.0 += 1;
+                dijkstra_maps.refresh(&world.state, &world.spatial_position);
+                let coarse_tick = clock.0 % COARSE_TICK_INTERVAL == 0;
 
-                let mut entities = vec![];
+                let mut entities: Vec<(EntityId, Simulation)> = vec![];
 
                 if let Some(&player_position) = world.state.get_position(player_id) {
-                    for PositionTreeObject { entity_at, .. } in world
-                        .spatial_position
-                        .locate_within_distance(player_position, 4000)
+                    for PositionTreeObject { index, entity_at } in
+                        world.spatial_position.locate_within_distance(
+                            player_position,
+                            COARSE_SIMULATION_RADIUS * COARSE_SIMULATION_RADIUS,
+                        )
                     {
-                        entities.push(*entity_at);
+                        match Simulation::at(player_position, *index) {
+                            Simulation::Full => entities.push((*entity_at, Simulation::Full)),
+                            Simulation::Coarse if coarse_tick => {
+                                entities.push((*entity_at, Simulation::Coarse))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                let mut steps = vec![];
+                for (entity_at, simulation) in entities {
+                    if let Some(energy) = world.state.energy.get_mut(&entity_at) {
+                        let ticks = last_simulated.catch_up(entity_at, simulation, clock.0);
+                        energy.0 += ENERGY_PER_TICK * ticks as i64;
+                        steps.push((entity_at, simulation, ticks));
                     }
                 }
 
-                for entity_at in entities {
-                    if let (Some(&energy), None) = (
-                        world.state.get_energy(entity_at),
-                        world.state.get_player(entity_at),
-                    ) {
-                        if energy.0 >= 0 {
-                            // Debug //
-                            // TODO: IA should compute next move for entity
-                            let delta = rng.gen_range(-1..=1);
-                            let x_or_y = rand::random();
-                            let action = ActionType::MoveBy {
+                // Entities back from a coarse or frozen stretch spend the
+                // energy of the ticks they missed, up to one action per tick
+                for (entity_at, simulation, ticks) in steps {
+                    if world.state.get_player(entity_at).is_some() {
+                        continue;
+                    }
+                    for _ in 0..ticks {
+                        match world.state.get_energy(entity_at) {
+                            Some(energy) if energy.0 >= 0 => {}
+                            _ => break,
+                        }
+                        let mut actions = match simulation {
+                            Simulation::Coarse => {
+                                ai::coarse_actions(&world.state, &dijkstra_maps, entity_at)
+                            }
+                            _ => ai::next_actions(
+                                &world.state,
+                                &world.spatial_position,
+                                &dijkstra_maps,
+                                entity_at,
+                                utility_trace.0,
+                                &mut rng,
+                            ),
+                        };
+                        if actions.is_empty() {
+                            actions.push(ActionType::Wait {
                                 entity_id: entity_at,
-                                dx: if x_or_y { delta } else { 0 },
-                                dy: if !x_or_y { delta } else { 0 },
                                 cost: 100,
-                            };
-
-                            //
+                            });
+                        }
 
+                        for action in actions {
                             world.enqueue_action(action);
-                            world.process_actions();
                         }
+                        world.process_actions();
                     }
                 }
             }
@@ -1,8 +1,23 @@
+use bevy::prelude::Color;
+use rand::Rng;
 use rstar::RTree;
 
-use crate::raw_loader::EntityTemplate;
+use crate::raw_loader::{EntityTemplate, TrapTemplate};
 
-use super::components::*;
+use super::{
+    allies::{self, MAX_ALLIES},
+    components::*,
+    map::TileMap,
+    morale::{self, CORNERED_DAMAGE},
+    stats::{self, Stats},
+};
+
+/// How far around itself an entity looks for hidden traps.
+const SEARCH_RADIUS: i64 = 2;
+/// Monsters this close to an alarm trap come to see what happened.
+const ALARM_RADIUS: i64 = 15;
+/// Energy lost climbing out of a pit, a whole turn.
+const PIT_CLIMB_COST: u32 = 100;
 
 #[derive(Debug)]
 pub enum ActionType {
@@ -30,13 +45,119 @@ pub enum ActionType {
         position: Position,
         glyph: Glyph,
         name: Name,
+        healing: Option<Healing>,
+        light: Option<LightSource>,
+        cost: u32,
+    },
+    CreateMap {
+        entity_id: EntityId,
+        map: TileMap,
+        cost: u32,
+    },
+    CreateStairs {
+        entity_id: EntityId,
+        position: Position,
+        stairs: Stairs,
+        cost: u32,
+    },
+    CreateDoor {
+        entity_id: EntityId,
+        position: Position,
+        door: Door,
+        cost: u32,
+    },
+    CreateKey {
+        entity_id: EntityId,
+        position: Position,
+        key: Key,
+        cost: u32,
+    },
+    CreateTrap {
+        entity_id: EntityId,
+        position: Position,
+        template: TrapTemplate,
+        cost: u32,
+    },
+    CreateFactionTable {
+        entity_id: EntityId,
+        table: FactionTable,
         cost: u32,
     },
+    RevealTiles {
+        map_id: EntityId,
+        tiles: Vec<Position>,
+    },
     DamageEntity {
         attacker_id: EntityId,
         target_id: EntityId,
         cost: u32,
     },
+    SwapPlaces {
+        entity_id: EntityId,
+        other_id: EntityId,
+        cost: u32,
+    },
+    AskAttackConfirmation {
+        entity_id: EntityId,
+        target_id: EntityId,
+    },
+    CancelAttackConfirmation {
+        entity_id: EntityId,
+    },
+    UpdateAwareness {
+        entity_id: EntityId,
+        awareness: Awareness,
+    },
+    JoinPack {
+        entity_id: EntityId,
+        leader_id: EntityId,
+    },
+    UpdateMorale {
+        entity_id: EntityId,
+        morale: Morale,
+    },
+    DeriveStats {
+        entity_id: EntityId,
+    },
+    Heal {
+        entity_id: EntityId,
+        amount: i64,
+    },
+    Summon {
+        entity_id: EntityId,
+        summoned_id: EntityId,
+        template: EntityTemplate,
+        cost: u32,
+    },
+    Recruit {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    GiveOrder {
+        entity_id: EntityId,
+        order: Order,
+        cost: u32,
+    },
+    RangedAttack {
+        attacker_id: EntityId,
+        target_id: EntityId,
+        cost: u32,
+    },
+    CastSpell {
+        caster_id: EntityId,
+        target_id: EntityId,
+        cost: u32,
+    },
+    Drink {
+        entity_id: EntityId,
+        item_id: EntityId,
+        cost: u32,
+    },
+    InflictDamage {
+        target_id: EntityId,
+        damage: i64,
+        cost: u32,
+    },
     Die {
         entity_id: EntityId,
         cost: u32,
@@ -49,6 +170,50 @@ pub enum ActionType {
         entity_id: EntityId,
         value: u32,
     },
+    Descend {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    Ascend {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    OpenDoor {
+        entity_id: EntityId,
+        door_id: EntityId,
+        cost: u32,
+    },
+    CloseDoor {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    LockDoor {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    UnlockDoor {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    TriggerTrap {
+        entity_id: EntityId,
+        trap_id: EntityId,
+        cost: u32,
+    },
+    Search {
+        entity_id: EntityId,
+        cost: u32,
+    },
+    Dig {
+        entity_id: EntityId,
+        dx: i64,
+        dy: i64,
+        cost: u32,
+    },
+    Disarm {
+        entity_id: EntityId,
+        cost: u32,
+    },
 }
 
 pub fn populate_action(
@@ -84,6 +249,67 @@ pub fn populate_action(
         } => {
             damage_entity(action, state, attacker_id, target_id);
         }
+        ActionType::CreateFactionTable {
+            entity_id, table, ..
+        } => action.insert_factiontable(entity_id, table),
+        ActionType::SwapPlaces {
+            entity_id,
+            other_id,
+            cost,
+        } => swap_places(action, state, entity_id, other_id, cost),
+        ActionType::AskAttackConfirmation {
+            entity_id,
+            target_id,
+        } => ask_attack_confirmation(action, state, entity_id, target_id),
+        ActionType::CancelAttackConfirmation { entity_id } => {
+            action.remove_attackconfirmation(entity_id)
+        }
+        ActionType::UpdateAwareness {
+            entity_id,
+            awareness,
+        } => action.insert_awareness(entity_id, awareness),
+        ActionType::JoinPack {
+            entity_id,
+            leader_id,
+        } => action.insert_pack(entity_id, Pack { leader: leader_id }),
+        ActionType::UpdateMorale { entity_id, morale } => action.insert_morale(entity_id, morale),
+        ActionType::DeriveStats { entity_id } => derive_stats(action, state, entity_id),
+        ActionType::Heal { entity_id, amount } => heal(action, state, entity_id, amount),
+        ActionType::Summon {
+            entity_id,
+            summoned_id,
+            template,
+            cost,
+        } => summon(action, state, entity_id, summoned_id, template, cost),
+        ActionType::Recruit { entity_id, cost } => {
+            recruit(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::GiveOrder {
+            entity_id,
+            order,
+            cost,
+        } => give_order(action, state, entity_id, order, cost),
+        ActionType::RangedAttack {
+            attacker_id,
+            target_id,
+            cost,
+        } => {
+            damage_entity(action, state, attacker_id, target_id);
+            action.insert_actioncost(attacker_id, cost.into());
+        }
+        ActionType::CastSpell {
+            caster_id,
+            target_id,
+            cost,
+        } => cast_spell(action, state, caster_id, target_id, cost),
+        ActionType::Drink {
+            entity_id,
+            item_id,
+            cost,
+        } => drink(action, state, entity_id, item_id, cost),
+        ActionType::InflictDamage {
+            target_id, damage, ..
+        } => inflict_damage(action, state, target_id, damage),
         ActionType::Die { entity_id, .. } => {
             die(action, state, entity_id);
         }
@@ -92,8 +318,36 @@ pub fn populate_action(
             position,
             glyph,
             name,
+            healing,
+            light,
+            ..
+        } => create_item(action, entity_id, position, glyph, name, healing, light),
+        ActionType::CreateMap { entity_id, map, .. } => create_map(action, entity_id, map),
+        ActionType::RevealTiles { map_id, tiles } => reveal_tiles(action, state, map_id, tiles),
+        ActionType::CreateStairs {
+            entity_id,
+            position,
+            stairs,
+            ..
+        } => create_stairs(action, entity_id, position, stairs),
+        ActionType::CreateDoor {
+            entity_id,
+            position,
+            door,
+            ..
+        } => create_door(action, entity_id, position, door),
+        ActionType::CreateKey {
+            entity_id,
+            position,
+            key,
+            ..
+        } => create_key(action, entity_id, position, key),
+        ActionType::CreateTrap {
+            entity_id,
+            position,
+            template,
             ..
-        } => create_item(action, entity_id, position, glyph, name),
+        } => create_trap(action, entity_id, position, template),
         ActionType::GrabItem { grabber_id, .. } => {
             grab_item(action, state, spatial_position, grabber_id)
         }
@@ -101,6 +355,46 @@ pub fn populate_action(
             decrease_energy(action, state, entity_id, value)
         }
         ActionType::Wait { entity_id, cost } => wait(action, entity_id, cost),
+        ActionType::Descend { entity_id, cost } => take_stairs(
+            action,
+            state,
+            spatial_position,
+            entity_id,
+            Stairs::Down,
+            cost,
+        ),
+        ActionType::Ascend { entity_id, cost } => {
+            take_stairs(action, state, spatial_position, entity_id, Stairs::Up, cost)
+        }
+        ActionType::OpenDoor {
+            entity_id,
+            door_id,
+            cost,
+        } => open_door(action, state, entity_id, door_id, cost),
+        ActionType::CloseDoor { entity_id, cost } => {
+            close_doors(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::LockDoor { entity_id, cost } => {
+            lock_doors(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::UnlockDoor { entity_id, cost } => {
+            unlock_doors(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::TriggerTrap {
+            entity_id, trap_id, ..
+        } => trigger_trap(action, state, spatial_position, entity_id, trap_id),
+        ActionType::Search { entity_id, cost } => {
+            search(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::Disarm { entity_id, cost } => {
+            disarm(action, state, spatial_position, entity_id, cost)
+        }
+        ActionType::Dig {
+            entity_id,
+            dx,
+            dy,
+            cost,
+        } => dig(action, state, entity_id, dx, dy, cost),
     }
 }
 
@@ -114,7 +408,10 @@ fn create_entity(
 ) {
     action.insert_position(entity_id, position);
     action.insert_attack(entity_id, template.attack);
-    action.insert_health(entity_id, template.health);
+    if let Some(health) = template.health {
+        action.insert_health(entity_id, health);
+        action.insert_maxhealth(entity_id, MaxHealth(health.0));
+    }
     action.insert_initiative(entity_id, template.initiative);
     action.insert_glyph(entity_id, template.glyph);
     action.insert_name(entity_id, template.name);
@@ -122,10 +419,73 @@ fn create_entity(
     action.insert_actioncost(entity_id, 0.into());
     if is_player {
         action.insert_player(entity_id, Player);
+    } else {
+        action.insert_aibrain(entity_id, template.ai.unwrap_or(AiBrain::Wanderer));
+        action.insert_awareness(entity_id, Awareness::default());
+        action.insert_morale(entity_id, template.morale.unwrap_or_default());
     }
     if is_solid {
         action.insert_solid(entity_id, Solid);
     }
+    if let Some(faction) = template.faction {
+        action.insert_faction(entity_id, faction);
+    }
+    if let Some(perception) = template.perception {
+        action.insert_perception(entity_id, perception);
+    }
+    if let Some(spell) = template.spell {
+        action.insert_spell(entity_id, spell);
+    }
+    if let Some(utility) = template.utility {
+        action.insert_utilityai(entity_id, utility);
+    }
+    if let Some(light) = template.light {
+        action.insert_lightsource(entity_id, light);
+    }
+    if let Some(stats) = template.stats {
+        insert_stats(action, entity_id, stats, template.initiative);
+        let max_health = stats.derive(template.initiative).max_health;
+        action.insert_health(entity_id, Health(max_health.0));
+    }
+}
+
+fn insert_stats(action: &mut Action, entity_id: EntityId, stats: Stats, initiative: Initiative) {
+    action.insert_might(entity_id, stats.might);
+    action.insert_agility(entity_id, stats.agility);
+    action.insert_vitality(entity_id, stats.vitality);
+    action.insert_will(entity_id, stats.will);
+    insert_derived_stats(action, entity_id, stats, initiative);
+}
+
+fn insert_derived_stats(
+    action: &mut Action,
+    entity_id: EntityId,
+    stats: Stats,
+    initiative: Initiative,
+) {
+    let derived = stats.derive(initiative);
+    action.insert_maxhealth(entity_id, derived.max_health);
+    action.insert_accuracy(entity_id, derived.accuracy);
+    action.insert_evasion(entity_id, derived.evasion);
+    action.insert_carrycapacity(entity_id, derived.carry_capacity);
+    action.insert_speed(entity_id, derived.speed);
+}
+
+/// Recomputes the values following from the stats of `entity_id`, keeping
+/// its health under the new max.
+fn derive_stats(action: &mut Action, state: &GameState, entity_id: EntityId) {
+    if let (Some(stats), Some(&initiative)) = (
+        stats::stats_of(state, entity_id),
+        state.get_initiative(entity_id),
+    ) {
+        insert_derived_stats(action, entity_id, stats, initiative);
+        let max_health = stats.derive(initiative).max_health;
+        if let Some(health) = state.get_health(entity_id) {
+            if health.0 > max_health.0 {
+                action.insert_health(entity_id, Health(max_health.0));
+            }
+        }
+    }
 }
 
 fn create_item(
@@ -134,11 +494,115 @@ fn create_item(
     position: Position,
     glyph: Glyph,
     name: Name,
+    healing: Option<Healing>,
+    light: Option<LightSource>,
 ) {
     action.insert_position(entity_id, position);
     action.insert_glyph(entity_id, glyph);
     action.insert_name(entity_id, name);
     action.insert_item(entity_id, Item);
+    if let Some(healing) = healing {
+        action.insert_healing(entity_id, healing);
+    }
+    if let Some(light) = light {
+        action.insert_lightsource(entity_id, light);
+    }
+}
+
+fn create_trap(
+    action: &mut Action,
+    entity_id: EntityId,
+    position: Position,
+    template: TrapTemplate,
+) {
+    action.insert_position(entity_id, position);
+    action.insert_name(entity_id, template.name);
+    action.insert_glyph(entity_id, template.glyph);
+    action.insert_trap(entity_id, template.trap);
+    action.insert_hidden(entity_id, Hidden);
+}
+
+fn create_map(action: &mut Action, entity_id: EntityId, map: TileMap) {
+    action.insert_tilemap(entity_id, map);
+}
+
+fn create_door(action: &mut Action, entity_id: EntityId, position: Position, door: Door) {
+    action.insert_position(entity_id, position);
+    action.insert_name(entity_id, Name("Door".to_owned()));
+    action.insert_glyph(entity_id, door_glyph(door.state));
+    if door.state != DoorState::Open {
+        action.insert_solid(entity_id, Solid);
+        action.insert_opaque(entity_id, Opaque);
+    }
+    action.insert_door(entity_id, door);
+}
+
+fn create_key(action: &mut Action, entity_id: EntityId, position: Position, key: Key) {
+    action.insert_position(entity_id, position);
+    action.insert_glyph(
+        entity_id,
+        Glyph {
+            character: '-',
+            color: Color::YELLOW,
+        },
+    );
+    action.insert_name(entity_id, Name("Key".to_owned()));
+    action.insert_item(entity_id, Item);
+    action.insert_key(entity_id, key);
+}
+
+fn reveal_tiles(action: &mut Action, state: &GameState, map_id: EntityId, tiles: Vec<Position>) {
+    if let Some(map) = state.get_tilemap(map_id) {
+        let mut map = map.clone();
+        for position in tiles {
+            map.reveal(position);
+        }
+        action.insert_tilemap(map_id, map);
+    }
+}
+
+/// Hits the tile next to the entity as hard as it attacks.
+fn dig(action: &mut Action, state: &GameState, entity_id: EntityId, dx: i64, dy: i64, cost: u32) {
+    if let (Some(map_id), Some(map), Some(position), Some(attack)) = (
+        state.map_id(),
+        state.map(),
+        state.get_position(entity_id),
+        state.get_attack(entity_id),
+    ) {
+        let target = Position {
+            x: position.x + dx,
+            y: position.y + dy,
+        };
+        let tile = map.get(target);
+        if tile.hit_points().is_none() {
+            println!("{tile:?} at pos: {target:?} can't be dug");
+            return;
+        }
+
+        let mut map = map.clone();
+        if map.dig(target, attack.0) {
+            println!("{tile:?} at pos: {target:?} collapses");
+        }
+        action.insert_tilemap(map_id, map);
+        action.insert_actioncost(entity_id, cost.into());
+    }
+}
+
+fn create_stairs(action: &mut Action, entity_id: EntityId, position: Position, stairs: Stairs) {
+    let (character, name) = match stairs {
+        Stairs::Down => ('>', "Stairs down"),
+        Stairs::Up => ('<', "Stairs up"),
+    };
+    action.insert_position(entity_id, position);
+    action.insert_glyph(
+        entity_id,
+        Glyph {
+            character,
+            color: Color::WHITE,
+        },
+    );
+    action.insert_name(entity_id, Name(name.to_owned()));
+    action.insert_stairs(entity_id, stairs);
 }
 
 fn damage_entity(
@@ -147,7 +611,25 @@ fn damage_entity(
     attacker_id: EntityId,
     target_id: EntityId,
 ) {
-    if let Some(attack) = state.get_attack(attacker_id) {
+    // A confirmed attack on a neutral creature has to be confirmed again
+    if state.get_attackconfirmation(attacker_id) == Some(&AttackConfirmation(target_id)) {
+        action.remove_attackconfirmation(attacker_id);
+    }
+    if let Some(&attack) = state.get_attack(attacker_id) {
+        // Cornered monsters fight back harder
+        let attack = match state.get_morale(attacker_id) {
+            Some(morale) if morale.cornered => Attack(attack.0 * CORNERED_DAMAGE / 100),
+            _ => attack,
+        };
+        let hit_chance = stats::hit_chance(state, attacker_id, target_id);
+        if rand::thread_rng().gen_range(0..100) >= hit_chance {
+            if let (Some(attacker_name), Some(target_name)) =
+                (state.get_name(attacker_id), state.get_name(target_id))
+            {
+                println!("{attacker_name} {attacker_id} misses {target_name} {target_id}");
+            }
+            return;
+        }
         if let Some(health) = state.get_health(target_id) {
             let new_health = health.0 - attack.0;
             if let (Some(attacker_name), Some(target_name)) =
@@ -162,6 +644,199 @@ fn damage_entity(
     }
 }
 
+fn swap_places(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    other_id: EntityId,
+    cost: u32,
+) {
+    if let (Some(&position), Some(&other_position)) =
+        (state.get_position(entity_id), state.get_position(other_id))
+    {
+        if let (Some(name), Some(other_name)) =
+            (state.get_name(entity_id), state.get_name(other_id))
+        {
+            println!("{name} {entity_id} swaps places with {other_name} {other_id}");
+        }
+        action.insert_position(entity_id, other_position);
+        action.insert_position(other_id, position);
+        action.insert_actioncost(entity_id, cost.into());
+    }
+}
+
+/// Brings a creature next to `entity_id` to fight for it.
+fn summon(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    summoned_id: EntityId,
+    template: EntityTemplate,
+    cost: u32,
+) {
+    if allies::allies_of(state, entity_id).count() >= MAX_ALLIES {
+        println!("Nothing answers the call");
+        return;
+    }
+    let position = match state
+        .get_position(entity_id)
+        .and_then(|&position| allies::free_spot_near(state, position, 1))
+    {
+        Some(position) => position,
+        None => {
+            println!("There is no room for a summoning");
+            return;
+        }
+    };
+
+    println!("{} answers the call", template.name);
+    create_entity(action, summoned_id, position, false, true, template);
+    make_ally(action, state, entity_id, summoned_id);
+    action.insert_actioncost(entity_id, cost.into());
+}
+
+/// Talks a neutral creature next to `entity_id` into following it.
+fn recruit(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    if allies::allies_of(state, entity_id).count() >= MAX_ALLIES {
+        println!("Nobody else will follow");
+        return;
+    }
+
+    let recruit = adjacent_entities(state, spatial_position, entity_id)
+        .into_iter()
+        .find(|&other_id| {
+            state.get_aibrain(other_id).is_some()
+                && state.get_allegiance(other_id).is_none()
+                && state.relation(entity_id, other_id) == Relation::Neutral
+        });
+    if let Some(recruit_id) = recruit {
+        if let Some(name) = state.get_name(recruit_id) {
+            println!("{name} {recruit_id} joins {entity_id}");
+        }
+        if state.get_pack(recruit_id).is_some() {
+            scatter_pack(action, state, recruit_id);
+            action.remove_pack(recruit_id);
+        }
+        make_ally(action, state, entity_id, recruit_id);
+        action.insert_actioncost(entity_id, cost.into());
+    } else {
+        println!("There is nobody to recruit");
+    }
+}
+
+fn make_ally(action: &mut Action, state: &GameState, owner_id: EntityId, ally_id: EntityId) {
+    action.insert_allegiance(
+        ally_id,
+        Allegiance {
+            owner: owner_id,
+            order: Order::Follow,
+        },
+    );
+    if let Some(faction) = state.get_faction(owner_id) {
+        action.insert_faction(ally_id, faction.clone());
+    }
+}
+
+fn give_order(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    order: Order,
+    cost: u32,
+) {
+    for ally_id in allies::allies_of(state, entity_id) {
+        action.insert_allegiance(
+            ally_id,
+            Allegiance {
+                owner: entity_id,
+                order,
+            },
+        );
+    }
+    println!("{entity_id} orders its allies to {order:?}");
+    action.insert_actioncost(entity_id, cost.into());
+}
+
+fn ask_attack_confirmation(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    target_id: EntityId,
+) {
+    if let Some(target_name) = state.get_name(target_id) {
+        println!("{target_name} {target_id} is not hostile, bump into it again to attack it");
+    }
+    action.insert_attackconfirmation(entity_id, AttackConfirmation(target_id));
+}
+
+fn inflict_damage(action: &mut Action, state: &GameState, target_id: EntityId, damage: i64) {
+    if let Some(health) = state.get_health(target_id) {
+        let new_health = health.0 - damage;
+        if let Some(target_name) = state.get_name(target_id) {
+            println!(
+                "{target_name} {target_id} takes {damage} damage! {target_name} {target_id} is now at {new_health} HP"
+            );
+        }
+        action.insert_health(target_id, Health(new_health));
+    }
+}
+
+fn cast_spell(
+    action: &mut Action,
+    state: &GameState,
+    caster_id: EntityId,
+    target_id: EntityId,
+    cost: u32,
+) {
+    if let Some(spell) = state.get_spell(caster_id) {
+        if let Some(name) = state.get_name(caster_id) {
+            println!("{name} {caster_id} casts a bolt at {target_id}");
+        }
+        let damage = stats::spell_damage(state, caster_id, spell.damage);
+        inflict_damage(action, state, target_id, damage);
+        action.insert_actioncost(caster_id, cost.into());
+    }
+}
+
+/// Drinks a healing item carried by `entity_id`, up to its max health.
+fn drink(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    item_id: EntityId,
+    cost: u32,
+) {
+    let carried = state.get_carriedby(item_id) == Some(&CarriedBy(entity_id));
+    if let (true, Some(healing)) = (carried, state.get_healing(item_id)) {
+        if let Some(name) = state.get_name(entity_id) {
+            println!("{name} {entity_id} drinks");
+        }
+        heal(action, state, entity_id, healing.0);
+        action.remove_all(item_id);
+        action.insert_actioncost(entity_id, cost.into());
+    }
+}
+
+/// Gives health back to `entity_id`, up to its max health.
+fn heal(action: &mut Action, state: &GameState, entity_id: EntityId, amount: i64) {
+    if let Some(health) = state.get_health(entity_id) {
+        let max_health = state.get_maxhealth(entity_id).map_or(i64::MAX, |max| max.0);
+        let new_health = (health.0 + amount).min(max_health).max(health.0);
+        if new_health != health.0 {
+            if let Some(name) = state.get_name(entity_id) {
+                println!("{name} {entity_id} is now at {new_health} HP");
+            }
+            action.insert_health(entity_id, Health(new_health));
+        }
+    }
+}
+
 fn wait(action: &mut Action, entity_id: EntityId, cost: u32) {
     action.insert_actioncost(entity_id, cost.into());
 }
@@ -190,9 +865,22 @@ fn die(action: &mut Action, state: &GameState, entity_id: EntityId) {
     if let Some(name) = state.get_name(entity_id) {
         println!("{name} {entity_id} died");
     }
+    for (other_id, morale) in morale::shaken_by_death(state, entity_id) {
+        action.insert_morale(other_id, morale);
+    }
+    scatter_pack(action, state, entity_id);
     action.remove_all(entity_id);
 }
 
+/// The pack of a leader that is gone scatters.
+fn scatter_pack(action: &mut Action, state: &GameState, leader_id: EntityId) {
+    for (&member_id, pack) in state.pack.iter() {
+        if pack.leader == leader_id && member_id != leader_id {
+            action.remove_pack(member_id);
+        }
+    }
+}
+
 fn grab_item(
     action: &mut Action,
     state: &GameState,
@@ -200,9 +888,17 @@ fn grab_item(
     grabber_id: EntityId,
 ) {
     if let Some(&position) = state.get_position(grabber_id) {
+        let mut room = stats::carry_room(state, grabber_id);
         for &PositionTreeObject { entity_at, .. } in spatial_position.locate_all_at_point(&position)
         {
             if state.get_item(entity_at).is_some() {
+                if room <= 0 {
+                    if let Some(grabber_name) = state.get_name(grabber_id) {
+                        println!("{grabber_name} cannot carry anything more");
+                    }
+                    return;
+                }
+                room -= 1;
                 if let (Some(grabber_name), Some(grabbed_name)) = (state.get_name(grabber_id), state.get_name(entity_at)) {
                     println!("{grabber_name} grabbed {grabbed_name}");
                 }
@@ -213,6 +909,27 @@ fn grab_item(
     }
 }
 
+fn take_stairs(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    stairs: Stairs,
+    cost: u32,
+) {
+    if let Some(&position) = state.get_position(entity_id) {
+        for &PositionTreeObject { entity_at, .. } in spatial_position.locate_all_at_point(&position)
+        {
+            if state.get_stairs(entity_at) == Some(&stairs) {
+                action.insert_takingstairs(entity_id, TakingStairs(stairs));
+                action.insert_actioncost(entity_id, cost.into());
+                return;
+            }
+        }
+    }
+    println!("There are no {stairs:?} stairs here");
+}
+
 fn decrease_energy(action: &mut Action, state: &GameState, entity_id: EntityId, value: u32) {
     if let Some(energy) = state.get_energy(entity_id) {
         action.insert_actioncost(entity_id, 0.into());
@@ -220,3 +937,296 @@ fn decrease_energy(action: &mut Action, state: &GameState, entity_id: EntityId,
         action.insert_energy(entity_id, new_energy.into());
     }
 }
+
+fn door_glyph(door_state: DoorState) -> Glyph {
+    let character = match door_state {
+        DoorState::Open => '\'',
+        DoorState::Closed | DoorState::Locked => '+',
+    };
+    Glyph {
+        character,
+        color: Color::rgb(0.6, 0.4, 0.2),
+    }
+}
+
+/// Entities standing on or next to `entity_id`, itself excluded.
+fn adjacent_entities(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+) -> Vec<EntityId> {
+    let mut entities = Vec::new();
+    if let Some(&position) = state.get_position(entity_id) {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let around = Position {
+                    x: position.x + dx,
+                    y: position.y + dy,
+                };
+                for &PositionTreeObject { entity_at, .. } in
+                    spatial_position.locate_all_at_point(&around)
+                {
+                    if entity_at != entity_id {
+                        entities.push(entity_at);
+                    }
+                }
+            }
+        }
+    }
+    entities
+}
+
+/// Doors on the 8 tiles around `entity_id`.
+fn adjacent_doors(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+) -> Vec<(EntityId, Door)> {
+    adjacent_entities(state, spatial_position, entity_id)
+        .into_iter()
+        .filter_map(|door_id| state.get_door(door_id).map(|&door| (door_id, door)))
+        .collect()
+}
+
+pub fn carries_key(state: &GameState, carrier_id: EntityId, key: u64) -> bool {
+    state.carriedby.iter().any(|(&item_id, carried_by)| {
+        carried_by.0 == carrier_id && state.get_key(item_id) == Some(&Key(key))
+    })
+}
+
+fn set_door_state(action: &mut Action, door_id: EntityId, door: Door, door_state: DoorState) {
+    action.insert_door(
+        door_id,
+        Door {
+            state: door_state,
+            ..door
+        },
+    );
+    action.insert_glyph(door_id, door_glyph(door_state));
+    if door_state == DoorState::Open {
+        action.remove_solid(door_id);
+        action.remove_opaque(door_id);
+    } else {
+        action.insert_solid(door_id, Solid);
+        action.insert_opaque(door_id, Opaque);
+    }
+}
+
+fn open_door(
+    action: &mut Action,
+    state: &GameState,
+    entity_id: EntityId,
+    door_id: EntityId,
+    cost: u32,
+) {
+    if let Some(&door) = state.get_door(door_id) {
+        if door.state == DoorState::Closed {
+            if let Some(name) = state.get_name(entity_id) {
+                println!("{name} {entity_id} opens the door");
+            }
+            set_door_state(action, door_id, door, DoorState::Open);
+            action.insert_actioncost(entity_id, cost.into());
+        }
+    }
+}
+
+fn close_doors(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    for (door_id, door) in adjacent_doors(state, spatial_position, entity_id) {
+        if door.state != DoorState::Open {
+            continue;
+        }
+        // Something stands in the doorway
+        if let Some(position) = state.get_position(door_id) {
+            if spatial_position.locate_all_at_point(position).count() > 1 {
+                println!("Something is blocking the door");
+                continue;
+            }
+        }
+        set_door_state(action, door_id, door, DoorState::Closed);
+        action.insert_actioncost(entity_id, cost.into());
+    }
+}
+
+fn lock_doors(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    for (door_id, door) in adjacent_doors(state, spatial_position, entity_id) {
+        if let (DoorState::Closed, Some(key)) = (door.state, door.key) {
+            if carries_key(state, entity_id, key) {
+                println!("The door is now locked");
+                set_door_state(action, door_id, door, DoorState::Locked);
+                action.insert_actioncost(entity_id, cost.into());
+            } else {
+                println!("You don't have the key of this door");
+            }
+        }
+    }
+}
+
+fn unlock_doors(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    for (door_id, door) in adjacent_doors(state, spatial_position, entity_id) {
+        if let (DoorState::Locked, Some(key)) = (door.state, door.key) {
+            if carries_key(state, entity_id, key) {
+                println!("The door is now unlocked");
+                set_door_state(action, door_id, door, DoorState::Closed);
+                action.insert_actioncost(entity_id, cost.into());
+            } else {
+                println!("You don't have the key of this door");
+            }
+        }
+    }
+}
+
+/// Chance out of 100 for an entity with `skill` to beat the difficulty of a
+/// trap.
+fn trap_skill_chance(skill: u32, difficulty: u32) -> u32 {
+    skill.saturating_sub(difficulty).clamp(5, 95)
+}
+
+fn trigger_trap(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    trap_id: EntityId,
+) {
+    if let (Some(trap), Some(name)) = (state.get_trap(trap_id), state.get_name(entity_id)) {
+        if state.get_hidden(trap_id).is_some() {
+            action.remove_hidden(trap_id);
+        }
+
+        match trap.kind {
+            TrapKind::Pit => {
+                println!("{name} {entity_id} falls into a pit and has to climb out");
+                decrease_energy(action, state, entity_id, PIT_CLIMB_COST);
+            }
+            TrapKind::Dart => println!("A dart hits {name} {entity_id}"),
+            TrapKind::Teleport => {
+                if let Some(position) = random_free_position(state, spatial_position) {
+                    println!("{name} {entity_id} is teleported to pos: {position:?}");
+                    action.insert_position(entity_id, position);
+                }
+            }
+            TrapKind::Alarm => {
+                println!("An alarm blares as {name} {entity_id} steps on a trap!");
+                if let Some(&position) = state.get_position(trap_id) {
+                    alert_monsters(action, state, spatial_position, position);
+                }
+            }
+        }
+
+        if trap.kind.is_single_use() {
+            action.remove_all(trap_id);
+        }
+    }
+}
+
+/// Makes the monsters around `position` come to have a look.
+fn alert_monsters(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    position: Position,
+) {
+    for &PositionTreeObject { entity_at, .. } in
+        spatial_position.locate_within_distance(position, ALARM_RADIUS * ALARM_RADIUS)
+    {
+        if let Some(&awareness) = state.get_awareness(entity_at) {
+            action.insert_awareness(entity_at, awareness.alerted_to(position));
+        }
+    }
+}
+
+/// Random walkable tile of the map with nothing on it.
+fn random_free_position(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+) -> Option<Position> {
+    let map = state.map()?;
+    let free: Vec<_> = map
+        .tiles()
+        .filter(|&(position, tile)| {
+            !tile.is_blocking()
+                && tile.hazard().is_none()
+                && spatial_position
+                    .locate_all_at_point(&position)
+                    .next()
+                    .is_none()
+        })
+        .map(|(position, _)| position)
+        .collect();
+
+    if free.is_empty() {
+        None
+    } else {
+        Some(free[rand::thread_rng().gen_range(0..free.len())])
+    }
+}
+
+fn search(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    let mut rng = rand::thread_rng();
+
+    if let Some(&position) = state.get_position(entity_id) {
+        for &PositionTreeObject { index, entity_at } in
+            spatial_position.locate_within_distance(position, SEARCH_RADIUS * SEARCH_RADIUS)
+        {
+            if let (Some(trap), Some(_)) = (state.get_trap(entity_at), state.get_hidden(entity_at))
+            {
+                let skill = stats::search_skill(state, entity_id);
+                if rng.gen_range(0..100) < trap_skill_chance(skill, trap.detection) {
+                    if let Some(name) = state.get_name(entity_at) {
+                        println!("Found a {name} at pos: {index:?}");
+                    }
+                    action.remove_hidden(entity_at);
+                }
+            }
+        }
+        action.insert_actioncost(entity_id, cost.into());
+    }
+}
+
+fn disarm(
+    action: &mut Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    cost: u32,
+) {
+    let mut rng = rand::thread_rng();
+
+    for trap_id in adjacent_entities(state, spatial_position, entity_id) {
+        // Nobody disarms a trap they don't know about
+        if let (Some(trap), None) = (state.get_trap(trap_id), state.get_hidden(trap_id)) {
+            let skill = stats::disarm_skill(state, entity_id);
+            if rng.gen_range(0..100) < trap_skill_chance(skill, trap.disarm) {
+                println!("The trap is disarmed");
+                action.remove_all(trap_id);
+            } else {
+                println!("You fail to disarm the trap");
+            }
+            action.insert_actioncost(entity_id, cost.into());
+        }
+    }
+}
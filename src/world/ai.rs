@@ -0,0 +1,564 @@
+use rand::{rngs::ThreadRng, Rng};
+use rstar::RTree;
+
+use super::{
+    actions::ActionType,
+    allies,
+    components::{
+        AiBrain, Alertness, Awareness, Candidate, EntityId, GameState, Order, Position,
+        PositionTreeObject, UtilityAi,
+    },
+    dijkstra::DijkstraMaps,
+    morale::{self, HEALTH_RECOVERY},
+    pack,
+    pathfinding::{self, Passability},
+    perception::{self, distance},
+    stats,
+    utility::{self, Inputs},
+};
+
+/// Ranged attackers shoot up to this distance.
+const SHOOTING_RANGE: i64 = 6;
+/// Ranged attackers back off when the player gets this close.
+const KITING_DISTANCE: i64 = 2;
+/// Following allies stay this close to their owner.
+const FOLLOW_DISTANCE: i64 = 2;
+/// Following allies go after enemies this close to their owner.
+const DEFEND_RADIUS: i64 = 6;
+/// Guarding allies go after enemies this close to their post.
+const GUARD_RADIUS: i64 = 4;
+
+/// Everything a behaviour can look at to pick the next action of a monster.
+pub struct AiContext<'a> {
+    pub state: &'a GameState,
+    pub spatial_position: &'a RTree<PositionTreeObject>,
+    pub dijkstra_maps: &'a DijkstraMaps,
+    pub entity_id: EntityId,
+    pub position: Position,
+    /// What the monster knows of the player, after this turn's look around
+    pub awareness: Awareness,
+    /// Closest enemy the monster can see, the player or one of their allies
+    pub target: Option<(EntityId, Position)>,
+}
+
+impl<'a> AiContext<'a> {
+    fn new(
+        state: &'a GameState,
+        spatial_position: &'a RTree<PositionTreeObject>,
+        dijkstra_maps: &'a DijkstraMaps,
+        entity_id: EntityId,
+    ) -> Option<Self> {
+        let position = *state.get_position(entity_id)?;
+        let (awareness, target) = perception::perceive(state, spatial_position, entity_id);
+
+        Some(AiContext {
+            state,
+            spatial_position,
+            dijkstra_maps,
+            entity_id,
+            position,
+            awareness,
+            target,
+        })
+    }
+
+    fn move_by(&self, (dx, dy): (i64, i64)) -> ActionType {
+        ActionType::MoveBy {
+            entity_id: self.entity_id,
+            dx,
+            dy,
+            cost: 100,
+        }
+    }
+
+    fn wait(&self) -> ActionType {
+        ActionType::Wait {
+            entity_id: self.entity_id,
+            cost: 100,
+        }
+    }
+
+    /// Step toward the target, bumping into it when next to it. Pack
+    /// members walk around each other to surround the player rather than
+    /// lining up behind one another.
+    fn approach(&self) -> Option<ActionType> {
+        let (target_id, target_position) = self.target?;
+        if distance(self.position, target_position) <= 1 {
+            return Some(self.move_by((
+                target_position.x - self.position.x,
+                target_position.y - self.position.y,
+            )));
+        }
+        // The approach map only leads to the player, not to their allies
+        if self.state.get_pack(self.entity_id).is_some()
+            || self.state.get_player(target_id).is_none()
+        {
+            if let Some(action) = self.walk_to(target_position) {
+                return Some(action);
+            }
+        }
+        self.dijkstra_maps
+            .approach
+            .downhill(self.position)
+            .map(|step| self.move_by(step))
+    }
+
+    /// Whether the monster is in a pack that is not together yet.
+    fn is_regrouping(&self) -> bool {
+        match (
+            self.state.get_pack(self.entity_id),
+            self.awareness.last_known_position,
+        ) {
+            (Some(pack), Some(target)) => !pack::is_gathered(self.state, pack.leader, target),
+            _ => false,
+        }
+    }
+
+    /// The leader waits for the pack, the others join it.
+    fn regroup(&self) -> ActionType {
+        let leader_position = self
+            .state
+            .get_pack(self.entity_id)
+            .and_then(|pack| self.state.get_position(pack.leader));
+        match leader_position {
+            Some(&leader_position)
+                if distance(self.position, leader_position) > pack::GATHER_RADIUS =>
+            {
+                self.walk_to(leader_position).unwrap_or_else(|| self.wait())
+            }
+            _ => self.wait(),
+        }
+    }
+
+    /// Step toward `target`, hitting it when next to it.
+    fn charge(&self, target: Position) -> ActionType {
+        if distance(self.position, target) <= 1 {
+            self.move_by((target.x - self.position.x, target.y - self.position.y))
+        } else {
+            self.walk_to(target).unwrap_or_else(|| self.wait())
+        }
+    }
+
+    fn flee(&self) -> Option<ActionType> {
+        self.dijkstra_maps
+            .flee
+            .downhill(self.position)
+            .map(|step| self.move_by(step))
+    }
+
+    /// Runs from the player, or turns on them when there is nowhere to go.
+    /// Returns whether the monster is cornered.
+    fn run_away(&self) -> (ActionType, bool) {
+        if self.target.is_none() {
+            // Out of sight, it catches its breath
+            return (self.wait(), false);
+        }
+        match self.flee() {
+            Some(action) => (action, false),
+            None => (self.approach().unwrap_or_else(|| self.wait()), true),
+        }
+    }
+
+    /// Step on the way to `goal`, walking around other monsters.
+    fn walk_to(&self, goal: Position) -> Option<ActionType> {
+        let passability = Passability::for_entity(self.entity_id);
+        pathfinding::find_path(
+            self.state,
+            self.spatial_position,
+            self.position,
+            goal,
+            &passability,
+        )
+        .ok()
+        .and_then(|path| path.next_step(self.position))
+        .map(|step| self.move_by(step))
+    }
+
+    /// Action matching a utility candidate, if it can be done right now.
+    fn candidate_action(&self, candidate: Candidate, rng: &mut ThreadRng) -> Option<ActionType> {
+        let target = self.target;
+        match candidate {
+            Candidate::Attack => {
+                let (_, target_position) = target?;
+                (distance(self.position, target_position) <= 1)
+                    .then(|| self.charge(target_position))
+            }
+            Candidate::MoveToward => self.approach(),
+            Candidate::Flee => target.and_then(|_| self.flee()),
+            Candidate::Cast => {
+                let (target_id, target_position) = target?;
+                let spell = self.state.get_spell(self.entity_id)?;
+                (distance(self.position, target_position) <= spell.range).then(|| {
+                    ActionType::CastSpell {
+                        caster_id: self.entity_id,
+                        target_id,
+                        cost: 100,
+                    }
+                })
+            }
+            Candidate::DrinkPotion => {
+                utility::carried_potion(self.state, self.entity_id).map(|item_id| {
+                    ActionType::Drink {
+                        entity_id: self.entity_id,
+                        item_id,
+                        cost: 100,
+                    }
+                })
+            }
+            Candidate::PickUp => utility::item_at(self.state, self.spatial_position, self.position)
+                .filter(|_| stats::carry_room(self.state, self.entity_id) > 0)
+                .map(|_| ActionType::GrabItem {
+                    grabber_id: self.entity_id,
+                    cost: 100,
+                }),
+            Candidate::Wander => Some(self.wander(rng)),
+            Candidate::Wait => Some(self.wait()),
+        }
+    }
+
+    /// Best scored candidate that can be done, if any scores above 0.
+    fn best_candidate(
+        &self,
+        utility: &UtilityAi,
+        trace: bool,
+        rng: &mut ThreadRng,
+    ) -> Option<ActionType> {
+        let inputs = Inputs::new(
+            self.state,
+            self.spatial_position,
+            self.entity_id,
+            self.position,
+            self.target.map(|(_, position)| position),
+        );
+
+        let mut scores = vec![];
+        let mut best: Option<(f32, ActionType)> = None;
+        for candidate in utility.candidates.iter() {
+            let action = match self.candidate_action(candidate.candidate, rng) {
+                Some(action) => action,
+                None => continue,
+            };
+            let score = candidate.score(&inputs);
+            scores.push(format!("{:?}={score:.2}", candidate.candidate));
+            if score > 0.0
+                && best
+                    .as_ref()
+                    .map_or(true, |&(best_score, _)| score > best_score)
+            {
+                best = Some((score, action));
+            }
+        }
+
+        if let (true, Some(name)) = (trace, self.state.get_name(self.entity_id)) {
+            let entity_id = self.entity_id;
+            let profile = &utility.profile;
+            println!("{name} {entity_id} [{profile}] {}", scores.join(" "));
+        }
+        best.map(|(_, action)| action)
+    }
+
+    fn wander(&self, rng: &mut ThreadRng) -> ActionType {
+        let step = (rng.gen_range(-1..=1), rng.gen_range(-1..=1));
+        if step == (0, 0) {
+            self.wait()
+        } else {
+            self.move_by(step)
+        }
+    }
+}
+
+/// Picks the next action of a monster. Every `AiBrain` has one behaviour.
+pub trait Behaviour {
+    fn next_action(&self, context: &AiContext, rng: &mut ThreadRng) -> ActionType;
+
+    /// Whether the monster goes to check where it last noticed the player.
+    fn investigates(&self) -> bool {
+        true
+    }
+}
+
+/// Runs to the player and hits them.
+pub struct MeleeChaser;
+
+impl Behaviour for MeleeChaser {
+    fn next_action(&self, context: &AiContext, rng: &mut ThreadRng) -> ActionType {
+        context.approach().unwrap_or_else(|| context.wander(rng))
+    }
+}
+
+/// Shoots from a distance, backing off when the player comes close.
+pub struct RangedKiter;
+
+impl Behaviour for RangedKiter {
+    fn next_action(&self, context: &AiContext, rng: &mut ThreadRng) -> ActionType {
+        let (target_id, target_position) = match context.target {
+            Some(target) => target,
+            None => return context.wander(rng),
+        };
+
+        let distance = distance(context.position, target_position);
+        if distance <= KITING_DISTANCE {
+            if let Some(action) = context.flee() {
+                return action;
+            }
+        }
+        if distance <= SHOOTING_RANGE {
+            return ActionType::RangedAttack {
+                attacker_id: context.entity_id,
+                target_id,
+                cost: 100,
+            };
+        }
+        context.approach().unwrap_or_else(|| context.wait())
+    }
+}
+
+/// Runs away as soon as it sees the player.
+pub struct Coward;
+
+impl Behaviour for Coward {
+    fn next_action(&self, context: &AiContext, rng: &mut ThreadRng) -> ActionType {
+        if context.target.is_some() {
+            // Cornered, it bites
+            context
+                .flee()
+                .or_else(|| context.approach())
+                .unwrap_or_else(|| context.wait())
+        } else {
+            context.wander(rng)
+        }
+    }
+}
+
+/// Never moves, but hits whoever comes next to it.
+pub struct Stationary;
+
+impl Behaviour for Stationary {
+    fn next_action(&self, context: &AiContext, _rng: &mut ThreadRng) -> ActionType {
+        match context.target {
+            Some((_, target_position)) if distance(context.position, target_position) <= 1 => {
+                context.approach().unwrap_or_else(|| context.wait())
+            }
+            _ => context.wait(),
+        }
+    }
+
+    fn investigates(&self) -> bool {
+        false
+    }
+}
+
+/// Walks around randomly.
+pub struct Wanderer;
+
+impl Behaviour for Wanderer {
+    fn next_action(&self, context: &AiContext, rng: &mut ThreadRng) -> ActionType {
+        context.wander(rng)
+    }
+}
+
+/// Follows the orders of its owner, whatever its `AiBrain`.
+pub struct Ally;
+
+impl Behaviour for Ally {
+    fn next_action(&self, context: &AiContext, _rng: &mut ThreadRng) -> ActionType {
+        let state = context.state;
+        let allegiance = match state.get_allegiance(context.entity_id) {
+            Some(&allegiance) => allegiance,
+            None => return context.wait(),
+        };
+        let sight = state
+            .get_perception(context.entity_id)
+            .map_or(perception::DEFAULT_PERCEPTION.sight, |perception| {
+                perception.sight
+            });
+        let enemy =
+            allies::nearest_enemy(state, context.spatial_position, context.entity_id, sight)
+                .map(|(_, position)| position);
+        let near = |center: Position, radius: i64| {
+            enemy.filter(|&position| distance(position, center) <= radius)
+        };
+
+        if let Order::Attack(target_id) = allegiance.order {
+            if let Some(&target) = state.get_position(target_id) {
+                return context.charge(target);
+            }
+        }
+
+        match allegiance.order {
+            Order::Stay => match near(context.position, 1) {
+                Some(enemy) => context.charge(enemy),
+                None => context.wait(),
+            },
+            Order::Guard(post) => match near(post, GUARD_RADIUS) {
+                Some(enemy) => context.charge(enemy),
+                None if context.position != post => {
+                    context.walk_to(post).unwrap_or_else(|| context.wait())
+                }
+                None => context.wait(),
+            },
+            // Attacked enemies that are gone leave the ally following
+            Order::Follow | Order::Attack(_) => {
+                let owner_position = match state.get_position(allegiance.owner) {
+                    Some(&position) => position,
+                    None => return context.wait(),
+                };
+                match near(owner_position, DEFEND_RADIUS) {
+                    Some(enemy) => context.charge(enemy),
+                    None if distance(context.position, owner_position) > FOLLOW_DISTANCE => context
+                        .walk_to(owner_position)
+                        .unwrap_or_else(|| context.wait()),
+                    None => context.wait(),
+                }
+            }
+        }
+    }
+}
+
+impl AiBrain {
+    pub fn behaviour(self) -> &'static dyn Behaviour {
+        match self {
+            AiBrain::MeleeChaser => &MeleeChaser,
+            AiBrain::RangedKiter => &RangedKiter,
+            AiBrain::Coward => &Coward,
+            AiBrain::Stationary => &Stationary,
+            AiBrain::Wanderer => &Wanderer,
+        }
+    }
+}
+
+/// Picks an action with the utility profile of the monster, or with its
+/// behaviour when it has none or nothing scores.
+fn decide(
+    behaviour: &dyn Behaviour,
+    context: &AiContext,
+    trace: bool,
+    rng: &mut ThreadRng,
+) -> ActionType {
+    context
+        .state
+        .get_utilityai(context.entity_id)
+        .and_then(|utility| context.best_candidate(utility, trace, rng))
+        .unwrap_or_else(|| behaviour.next_action(context, rng))
+}
+
+/// Next actions of an entity driven by an `AiBrain`, if it has one: what it
+/// noticed of the player, then what it does about it. With `trace`, utility
+/// scores are logged.
+pub fn next_actions(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    dijkstra_maps: &DijkstraMaps,
+    entity_id: EntityId,
+    trace: bool,
+    rng: &mut ThreadRng,
+) -> Vec<ActionType> {
+    let mut actions = vec![];
+
+    let (brain, mut context) = match (
+        state.get_aibrain(entity_id),
+        AiContext::new(state, spatial_position, dijkstra_maps, entity_id),
+    ) {
+        (Some(&brain), Some(context)) => (brain, context),
+        _ => return actions,
+    };
+    if state.get_allegiance(entity_id).is_some() {
+        actions.push(Ally.next_action(&context, rng));
+        return actions;
+    }
+    let behaviour = brain.behaviour();
+
+    let mut morale = morale::update(state, entity_id, context.target.is_some());
+    let fleeing = morale.map_or(false, |morale| morale.fleeing);
+
+    let awareness = context.awareness;
+    let investigates = awareness.is_searching() && behaviour.investigates();
+    let action = match awareness.last_known_position {
+        _ if awareness.alertness == Alertness::Sleeping => context.wait(),
+        _ if fleeing => {
+            let (action, cornered) = context.run_away();
+            if let Some(morale) = morale.as_mut() {
+                morale.cornered = cornered;
+            }
+            action
+        }
+        Some(_) if investigates && context.is_regrouping() => context.regroup(),
+        _ if context.target.is_some() => decide(behaviour, &context, trace, rng),
+        Some(goal) if investigates => match context.walk_to(goal) {
+            Some(action) => action,
+            None => {
+                // No way there, look around from here
+                context.awareness.last_known_position = None;
+                context.wander(rng)
+            }
+        },
+        None if investigates => context.wander(rng),
+        _ => decide(behaviour, &context, trace, rng),
+    };
+
+    if state.get_awareness(entity_id) != Some(&context.awareness) {
+        actions.push(ActionType::UpdateAwareness {
+            entity_id,
+            awareness: context.awareness,
+        });
+    }
+    if let Some(morale) = morale.filter(|&morale| state.get_morale(entity_id) != Some(&morale)) {
+        actions.push(ActionType::UpdateMorale { entity_id, morale });
+    }
+    if fleeing && context.target.is_none() {
+        actions.push(ActionType::Heal {
+            entity_id,
+            amount: HEALTH_RECOVERY,
+        });
+    }
+    if let Some((_, target_position)) = context.target {
+        actions.extend(pack::call_pack(state, entity_id, target_position));
+    }
+    actions.push(action);
+    actions
+}
+
+/// Cheap next actions of a monster far from the player: it walks down the
+/// shared maps when it is after the player, running from them or following
+/// them, and waits otherwise. Perception, morale and utility scores are left
+/// for when it comes closer.
+pub fn coarse_actions(
+    state: &GameState,
+    dijkstra_maps: &DijkstraMaps,
+    entity_id: EntityId,
+) -> Vec<ActionType> {
+    let position = match (state.get_aibrain(entity_id), state.get_position(entity_id)) {
+        (Some(_), Some(&position)) => position,
+        _ => return vec![],
+    };
+
+    let map = match (
+        state.get_allegiance(entity_id),
+        state.get_morale(entity_id),
+        state.get_awareness(entity_id),
+    ) {
+        (Some(allegiance), _, _) if allegiance.order == Order::Follow => {
+            Some(&dijkstra_maps.approach)
+        }
+        (Some(_), _, _) => None,
+        (None, Some(morale), _) if morale.fleeing => Some(&dijkstra_maps.flee),
+        (None, _, Some(awareness)) if awareness.alertness == Alertness::Hunting => {
+            Some(&dijkstra_maps.approach)
+        }
+        _ => None,
+    };
+
+    let action = match map.and_then(|map| map.downhill(position)) {
+        Some((dx, dy)) => ActionType::MoveBy {
+            entity_id,
+            dx,
+            dy,
+            cost: 100,
+        },
+        None => ActionType::Wait {
+            entity_id,
+            cost: 100,
+        },
+    };
+    vec![action]
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashSet, VecDeque};
+
+use rstar::RTree;
+
+use super::{
+    components::{DoorState, EntityId, GameState, Order, Position, PositionTreeObject, Relation},
+    pathfinding::Connectivity,
+    perception::{can_see, distance},
+};
+
+/// Most allies an entity can have at once.
+pub const MAX_ALLIES: usize = 3;
+/// Followers close to their owner when they take the stairs go along.
+pub const FOLLOW_RADIUS: i64 = 5;
+
+/// Allies of `owner_id`.
+pub fn allies_of(state: &GameState, owner_id: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    state
+        .allegiance
+        .iter()
+        .filter(move |(_, allegiance)| allegiance.owner == owner_id)
+        .map(|(&ally_id, _)| ally_id)
+}
+
+/// Allies of `owner_id` that go along when it takes the stairs.
+pub fn followers_of(state: &GameState, owner_id: EntityId) -> Vec<EntityId> {
+    let owner_position = match state.get_position(owner_id) {
+        Some(&position) => position,
+        None => return vec![],
+    };
+
+    let mut followers: Vec<_> = allies_of(state, owner_id)
+        .filter(|&ally_id| {
+            let following = state.get_allegiance(ally_id).map_or(false, |allegiance| {
+                matches!(allegiance.order, Order::Follow | Order::Attack(_))
+            });
+            let close = state.get_position(ally_id).map_or(false, |&position| {
+                distance(position, owner_position) <= FOLLOW_RADIUS
+            });
+            following && close
+        })
+        .collect();
+    followers.sort_by_key(|ally_id| ally_id.0);
+    followers
+}
+
+/// Closest creature hostile to `entity_id` that it can see.
+pub fn nearest_enemy(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+    sight: i64,
+) -> Option<(EntityId, Position)> {
+    let position = *state.get_position(entity_id)?;
+
+    spatial_position
+        .locate_within_distance(position, sight * sight)
+        .filter(|&&PositionTreeObject { entity_at, .. }| {
+            entity_at != entity_id
+                && state.get_health(entity_at).is_some()
+                && state.relation(entity_id, entity_at) == Relation::Hostile
+        })
+        .filter_map(|&PositionTreeObject { entity_at, .. }| {
+            let enemy_position = *state.get_position(entity_at)?;
+            can_see(state, spatial_position, position, enemy_position, sight)
+                .then(|| (entity_at, enemy_position))
+        })
+        .min_by_key(|&(enemy_id, enemy_position)| (distance(position, enemy_position), enemy_id.0))
+}
+
+/// Closest free tile to `position` that can be walked to from it, where an
+/// ally can be put.
+pub fn free_spot_near(state: &GameState, position: Position, radius: i64) -> Option<Position> {
+    let map = state.map()?;
+    let taken: HashSet<_> = state
+        .position
+        .iter()
+        .filter(|(&id, _)| state.get_solid(id).is_some())
+        .map(|(_, &position)| position)
+        .collect();
+    let shut_doors: HashSet<_> = state
+        .door
+        .iter()
+        .filter(|(_, door)| door.state != DoorState::Open)
+        .filter_map(|(&id, _)| state.get_position(id).copied())
+        .collect();
+    let walkable = |spot: Position| {
+        map.in_bounds(spot)
+            && !map.is_blocking(spot)
+            && map.get(spot).hazard().is_none()
+            && !shut_doors.contains(&spot)
+    };
+
+    // Search outward over walkable tiles, so that allies don't end up
+    // behind a wall
+    let mut seen = HashSet::from([position]);
+    let mut open = VecDeque::from([position]);
+    while let Some(spot) = open.pop_front() {
+        if walkable(spot) && !taken.contains(&spot) {
+            return Some(spot);
+        }
+        for &(dx, dy) in Connectivity::Eight.directions() {
+            let next = Position {
+                x: spot.x + dx,
+                y: spot.y + dy,
+            };
+            if distance(next, position) <= radius && walkable(next) && seen.insert(next) {
+                open.push_back(next);
+            }
+        }
+    }
+    None
+}
@@ -1,15 +1,19 @@
+use std::collections::HashMap;
+
 use bevy::prelude::{Color, Component};
 use bevy_inspector_egui::Inspectable;
 use derive_more::{From, Into, Display};
 use rstar::Point;
 use rule_system::register_components;
 
+use super::map::TileMap;
+
 #[derive(
     Debug, Clone, Copy, Eq, PartialEq, Hash, Component, Inspectable, Serialize, Deserialize, Display
 )]
 pub struct EntityId(pub u64);
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Position {
     pub x: i64,
     pub y: i64,
@@ -50,9 +54,45 @@ pub struct Attack(pub i64);
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
 pub struct Health(pub i64);
 
+/// Health is never healed above it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct MaxHealth(pub i64);
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
 pub struct Initiative(pub u32);
 
+/// Strength, adds to accuracy and to how much can be carried.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Might(pub i64);
+
+/// Nimbleness, adds to accuracy, evasion and speed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Agility(pub i64);
+
+/// Toughness, gives max health.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Vitality(pub i64);
+
+/// Strength of mind, adds to the damage of spells.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Will(pub i64);
+
+/// Chance to hit in percent, before the evasion of the target.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Accuracy(pub i64);
+
+/// Taken off the accuracy of attackers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Evasion(pub i64);
+
+/// Number of items that can be carried.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct CarryCapacity(pub i64);
+
+/// Initiative sped up by agility, divides the cost of actions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
+pub struct Speed(pub u32);
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Display, Into, From)]
 pub struct Name(pub String);
 
@@ -71,6 +111,10 @@ pub struct Solid;
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Item;
 
+/// Item that heals whoever drinks it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Healing(pub i64);
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct CarriedBy(pub EntityId);
 
@@ -80,12 +124,322 @@ pub struct Energy(pub i64);
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Display, Into, From)]
 pub struct ActionCost(pub u32);
 
-register_components!(
-    index EntityId,
-    components {
-        Health, Attack, Initiative, Glyph, Name, Player, Solid, Item, CarriedBy, Energy, ActionCost
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Stairs {
+    Down,
+    Up,
+}
+
+/// Set on an entity that used stairs, the level is swapped once the action
+/// has been processed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TakingStairs(pub Stairs);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DoorState {
+    Open,
+    Closed,
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Door {
+    pub state: DoorState,
+    /// Id of the key that fits the lock, doors without one can't be locked.
+    pub key: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Key(pub u64);
+
+/// Blocks the line of sight, like a closed door.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Opaque;
+
+/// Lights the tiles around the entity, or around whoever carries it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LightSource {
+    pub radius: i64,
+    pub color: Color,
+    /// Brightness at the center of the light, from 0 to 1.
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TrapKind {
+    Pit,
+    Dart,
+    Teleport,
+    Alarm,
+}
+
+impl TrapKind {
+    /// Whether the trap is spent once it goes off. A pit stays open, the
+    /// mechanism of the others only works once.
+    pub fn is_single_use(self) -> bool {
+        self != TrapKind::Pit
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub damage: i64,
+    /// Difficulty to spot the trap, removed from the searcher's chance.
+    pub detection: u32,
+    /// Difficulty to disarm the trap, removed from the disarmer's chance.
+    pub disarm: u32,
+}
+
+/// Not shown to the player until found, like a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Hidden;
+
+/// How a monster picks its actions, see `ai::Behaviour`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AiBrain {
+    MeleeChaser,
+    RangedKiter,
+    Coward,
+    Stationary,
+    Wanderer,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Faction(pub String);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Relation {
+    Allied,
+    Neutral,
+    Hostile,
+}
+
+/// How factions feel about each other, held by a single entity of the level.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FactionTable {
+    relations: HashMap<(Faction, Faction), Relation>,
+}
+
+impl FactionTable {
+    pub fn new(relations: impl IntoIterator<Item = (Faction, Faction, Relation)>) -> Self {
+        FactionTable {
+            relations: relations
+                .into_iter()
+                .map(|(faction, other, relation)| ((faction, other), relation))
+                .collect(),
+        }
     }
-    spatial {
-        Position
+
+    /// Relations go both ways, members of a faction are allies unless told
+    /// otherwise and unrelated factions leave each other alone.
+    pub fn relation(&self, faction: &Faction, other: &Faction) -> Relation {
+        self.relations
+            .get(&(faction.clone(), other.clone()))
+            .or_else(|| self.relations.get(&(other.clone(), faction.clone())))
+            .copied()
+            .unwrap_or(if faction == other {
+                Relation::Allied
+            } else {
+                Relation::Neutral
+            })
     }
+}
+
+/// How far a monster notices the player, by sight and by ear.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Perception {
+    pub sight: i64,
+    /// Noise goes through walls
+    pub hearing: i64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Alertness {
+    Sleeping,
+    Idle,
+    /// Heard something and goes to have a look
+    Alert,
+    /// Saw the player and goes after them
+    Hunting,
+}
+
+/// What a monster knows about the player.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Awareness {
+    pub alertness: Alertness,
+    pub last_known_position: Option<Position>,
+    /// Turns left searching around the last known position before giving up
+    pub search_turns: u32,
+}
+
+/// Monster spawned in a group, which follows the member with id `leader`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pack {
+    pub leader: EntityId,
+}
+
+/// How long a monster stands its ground before running away.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Morale {
+    /// Percentage of its max health under which it flees
+    pub flee_threshold: i64,
+    /// Morale of the monster when it is not shaken
+    pub courage: i64,
+    /// Lost when allies die around it, it flees when none is left
+    pub morale: i64,
+    pub fleeing: bool,
+    /// Fleeing with nowhere to go, it fights back harder
+    pub cornered: bool,
+}
+
+/// What an ally was told to do by its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Order {
+    Follow,
+    /// Hold the current position, only hitting what comes next to it
+    Stay,
+    Attack(EntityId),
+    /// Keep enemies away from a position
+    Guard(Position),
+}
+
+/// Summoned or recruited creature fighting for its `owner`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Allegiance {
+    pub owner: EntityId,
+    pub order: Order,
+}
+
+/// Bolt of magic an entity can throw at its enemies.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Spell {
+    pub damage: i64,
+    pub range: i64,
+}
+
+/// What a monster driven by utility scoring can do.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Candidate {
+    Attack,
+    MoveToward,
+    Flee,
+    Cast,
+    DrinkPotion,
+    PickUp,
+    Wander,
+    Wait,
+}
+
+/// Facts about the situation of a monster, all between 0 and 1.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ConsiderationInput {
+    /// Health over max health
+    Health,
+    /// Distance to the player over the view radius, 1 when out of sight
+    Distance,
+    Adjacent,
+    Visible,
+    /// Whether the player is within reach of the spell of the monster
+    InRange,
+    /// Whether the monster carries a healing item
+    Potion,
+    /// Whether an item lies under the monster
+    ItemHere,
+}
+
+/// Turns an input into a score between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear { slope: f32, offset: f32 },
+    /// 1 from the threshold on
+    Above(f32),
+    /// 1 under the threshold
+    Below(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Consideration {
+    pub input: ConsiderationInput,
+    pub curve: Curve,
+}
+
+/// A candidate action and how to score it: its weight times the score of
+/// every consideration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UtilityCandidate {
+    pub candidate: Candidate,
+    pub weight: f32,
+    pub considerations: Vec<Consideration>,
+}
+
+/// Monster that scores its candidate actions instead of following a fixed
+/// behaviour, with the profile they were read from in the raws.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UtilityAi {
+    pub profile: String,
+    pub candidates: Vec<UtilityCandidate>,
+}
+
+/// Neutral entity the player bumped into once, bumping it again attacks it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AttackConfirmation(pub EntityId);
+
+/// Registers the components with the field holding them in the `GameState`,
+/// and generates `GameState::move_components` from the same list so that no
+/// component is left behind when an entity changes level.
+macro_rules! game_components {
+    ($($component:ident: $field:ident),* $(,)?) => {
+        register_components!(
+            index EntityId,
+            components {
+                $($component),*
+            }
+            spatial {
+                Position
+            }
+        );
+
+        impl GameState {
+            /// Moves every component of `entity_id` but its position to `to`.
+            pub fn move_components(&mut self, entity_id: EntityId, to: &mut GameState) {
+                $(
+                    if let Some(component) = self.$field.remove(&entity_id) {
+                        to.$field.insert(entity_id, component);
+                    }
+                )*
+            }
+        }
+    };
+}
+
+game_components!(
+    Health: health, Attack: attack, Initiative: initiative, Glyph: glyph, Name: name,
+    Player: player, Solid: solid, Item: item, CarriedBy: carriedby, Energy: energy,
+    ActionCost: actioncost,
+    TileMap: tilemap, Stairs: stairs, TakingStairs: takingstairs, Door: door, Key: key,
+    Opaque: opaque, LightSource: lightsource, Trap: trap, Hidden: hidden,
+    AiBrain: aibrain, Faction: faction, FactionTable: factiontable,
+    AttackConfirmation: attackconfirmation, Perception: perception, Awareness: awareness,
+    Pack: pack, Morale: morale,
+    Allegiance: allegiance, MaxHealth: maxhealth, Healing: healing, Spell: spell,
+    UtilityAi: utilityai,
+    Might: might, Agility: agility, Vitality: vitality, Will: will, Accuracy: accuracy,
+    Evasion: evasion, CarryCapacity: carrycapacity, Speed: speed,
 );
+
+impl GameState {
+    /// How an entity feels about another, entities without a faction being
+    /// hostile to everyone.
+    pub fn relation(&self, entity_id: EntityId, other_id: EntityId) -> Relation {
+        match (
+            self.get_faction(entity_id),
+            self.get_faction(other_id),
+            self.factiontable.values().next(),
+        ) {
+            (Some(faction), Some(other), Some(table)) => table.relation(faction, other),
+            (Some(faction), Some(other), None) if faction == other => Relation::Allied,
+            _ => Relation::Hostile,
+        }
+    }
+}
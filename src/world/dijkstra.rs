@@ -0,0 +1,258 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use rstar::RTree;
+
+use super::{
+    components::{EntityId, GameState, Position, PositionTreeObject},
+    pathfinding::{self, Connectivity, Passability},
+};
+
+/// Fleeing monsters follow the approach map upside down, scaled so that
+/// running past the player toward a farther exit looks better than a corner.
+const FLEE_SCALE: f32 = -1.2;
+const CONNECTIVITY: Connectivity = Connectivity::Eight;
+
+/// Distance from every tile of the level to the closest of a set of goals,
+/// in move costs. Walking downhill leads to the goals.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DijkstraMap {
+    width: i64,
+    height: i64,
+    values: Vec<Option<i32>>,
+}
+
+impl DijkstraMap {
+    fn new(width: i64, height: i64) -> Self {
+        DijkstraMap {
+            width,
+            height,
+            values: vec![None; (width * height) as usize],
+        }
+    }
+
+    fn index(&self, position: Position) -> Option<usize> {
+        if position.x >= 0 && position.y >= 0 && position.x < self.width && position.y < self.height
+        {
+            Some((position.y * self.width + position.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Value of a tile, `None` when no goal can be reached from it.
+    pub fn get(&self, position: Position) -> Option<i32> {
+        self.index(position).and_then(|index| self.values[index])
+    }
+
+    /// Move toward the lowest neighbour of `from`, if it is lower than `from`.
+    pub fn downhill(&self, from: Position) -> Option<(i64, i64)> {
+        let mut best = self.get(from)?;
+        let mut best_move = None;
+
+        for &(dx, dy) in CONNECTIVITY.directions() {
+            let next = Position {
+                x: from.x + dx,
+                y: from.y + dy,
+            };
+            if let Some(value) = self.get(next) {
+                if value < best {
+                    best = value;
+                    best_move = Some((dx, dy));
+                }
+            }
+        }
+
+        best_move
+    }
+
+    /// Map built from goals with a starting value each, usually 0.
+    fn from_goals(costs: &StepCosts, goals: impl IntoIterator<Item = (Position, i32)>) -> Self {
+        let mut dijkstra_map = DijkstraMap::new(costs.width, costs.height);
+        for (position, value) in goals {
+            if let Some(index) = dijkstra_map.index(position) {
+                dijkstra_map.values[index] = Some(value);
+            }
+        }
+        dijkstra_map.relax(costs);
+        dijkstra_map
+    }
+
+    /// Lowers every tile to the value of its best neighbour plus the cost of
+    /// the move, starting from the tiles that already have a value.
+    fn relax(&mut self, costs: &StepCosts) {
+        let mut open: BinaryHeap<_> = self
+            .values
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| {
+                value.map(|value| {
+                    let index = index as i64;
+                    Reverse((value, index % self.width, index / self.width))
+                })
+            })
+            .collect();
+
+        while let Some(Reverse((value, x, y))) = open.pop() {
+            let current = Position { x, y };
+            if self.get(current).map_or(false, |known| known < value) {
+                continue;
+            }
+
+            for &(dx, dy) in CONNECTIVITY.directions() {
+                let next = Position {
+                    x: current.x + dx,
+                    y: current.y + dy,
+                };
+                let (index, cost) = match (self.index(next), costs.get(next)) {
+                    (Some(index), Some(cost)) => (index, cost),
+                    _ => continue,
+                };
+
+                let next_value = value + cost as i32;
+                if self.values[index].map_or(true, |known| next_value < known) {
+                    self.values[index] = Some(next_value);
+                    open.push(Reverse((next_value, next.x, next.y)));
+                }
+            }
+        }
+    }
+
+    /// Inverted and scaled copy of the map, relaxed again so that the way out
+    /// of dead ends is found.
+    fn flee(&self, costs: &StepCosts) -> Self {
+        let mut flee = DijkstraMap {
+            width: self.width,
+            height: self.height,
+            values: self
+                .values
+                .iter()
+                .map(|value| value.map(|value| (value as f32 * FLEE_SCALE) as i32))
+                .collect(),
+        };
+        flee.relax(costs);
+        flee
+    }
+}
+
+/// Cost of stepping on every tile of the level, shared by all the maps.
+struct StepCosts {
+    width: i64,
+    height: i64,
+    costs: Vec<Option<u32>>,
+}
+
+impl StepCosts {
+    fn compute(state: &GameState, spatial_position: &RTree<PositionTreeObject>) -> Self {
+        // Monsters don't block each other on shared maps
+        let passability = Passability {
+            connectivity: CONNECTIVITY,
+            avoid_solid: false,
+            ..Default::default()
+        };
+        let (width, height) = state
+            .map()
+            .map(|map| (map.width, map.height))
+            .unwrap_or((0, 0));
+
+        let mut costs = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                costs.push(pathfinding::step_cost(
+                    state,
+                    spatial_position,
+                    &passability,
+                    Position { x, y },
+                    None,
+                ));
+            }
+        }
+
+        StepCosts {
+            width,
+            height,
+            costs,
+        }
+    }
+
+    fn get(&self, position: Position) -> Option<u32> {
+        if position.x >= 0 && position.y >= 0 && position.x < self.width && position.y < self.height
+        {
+            self.costs[(position.y * self.width + position.x) as usize]
+        } else {
+            None
+        }
+    }
+}
+
+/// Maps shared by every monster and by the player automation, recomputed
+/// when the player moves.
+#[derive(Debug, Clone, Default)]
+pub struct DijkstraMaps {
+    /// Leads to the player
+    pub approach: DijkstraMap,
+    /// Leads away from the player
+    pub flee: DijkstraMap,
+    /// Leads to the closest tile the player has never seen
+    pub unexplored: DijkstraMap,
+    /// Leads to the closest item lying on the ground
+    pub items: DijkstraMap,
+    /// Level and player position the maps were computed for
+    computed_for: Option<(EntityId, Position)>,
+}
+
+impl DijkstraMaps {
+    pub fn compute(state: &GameState, spatial_position: &RTree<PositionTreeObject>) -> Self {
+        let player_position = state
+            .player
+            .keys()
+            .next()
+            .and_then(|&player_id| state.get_position(player_id))
+            .copied();
+        let costs = StepCosts::compute(state, spatial_position);
+
+        let approach = DijkstraMap::from_goals(&costs, player_position.map(|goal| (goal, 0)));
+        let flee = approach.flee(&costs);
+
+        let unexplored = match state.map() {
+            Some(map) => DijkstraMap::from_goals(
+                &costs,
+                map.tiles()
+                    .filter(|&(position, tile)| !tile.is_blocking() && !map.is_revealed(position))
+                    .map(|(position, _)| (position, 0)),
+            ),
+            None => DijkstraMap::default(),
+        };
+
+        let items = DijkstraMap::from_goals(
+            &costs,
+            state
+                .item
+                .keys()
+                .filter_map(|&item_id| state.get_position(item_id))
+                .map(|&position| (position, 0)),
+        );
+
+        DijkstraMaps {
+            approach,
+            flee,
+            unexplored,
+            items,
+            computed_for: state.map_id().zip(player_position),
+        }
+    }
+
+    /// Recomputes the maps if the player moved or changed level since the
+    /// last time.
+    pub fn refresh(&mut self, state: &GameState, spatial_position: &RTree<PositionTreeObject>) {
+        let player_position = state
+            .player
+            .keys()
+            .next()
+            .and_then(|&player_id| state.get_position(player_id))
+            .copied();
+
+        if self.computed_for != state.map_id().zip(player_position) {
+            *self = DijkstraMaps::compute(state, spatial_position);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use rstar::RTree;
+
+use super::components::{GameState, Position, PositionTreeObject};
+
+pub const VIEW_RADIUS: i64 = 12;
+
+/// True if the map or an `Opaque` entity stops the sight at `position`.
+pub fn blocks_sight(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    position: Position,
+) -> bool {
+    let blocked_by_map = state
+        .map()
+        .map(|map| map.get(position).blocks_sight())
+        .unwrap_or(false);
+
+    blocked_by_map
+        || spatial_position
+            .locate_all_at_point(&position)
+            .any(|&PositionTreeObject { entity_at, .. }| state.get_opaque(entity_at).is_some())
+}
+
+/// Tiles an entity standing at `origin` can see on the current level.
+pub fn field_of_view(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    origin: Position,
+    radius: i64,
+) -> HashSet<Position> {
+    compute_fov(origin, radius, |position| {
+        blocks_sight(state, spatial_position, position)
+    })
+}
+
+/// Symmetric shadowcasting: a tile is visible from `origin` if and only if
+/// `origin` is visible from it. Walls are visible but stop the light.
+///
+/// See https://www.albertford.com/shadowcasting/
+pub fn compute_fov(
+    origin: Position,
+    radius: i64,
+    blocks_sight: impl Fn(Position) -> bool,
+) -> HashSet<Position> {
+    let mut visible = HashSet::new();
+    visible.insert(origin);
+
+    for quadrant in [
+        Quadrant::North,
+        Quadrant::East,
+        Quadrant::South,
+        Quadrant::West,
+    ] {
+        let mut rows = vec![Row {
+            depth: 1,
+            start_slope: Slope::new(-1, 1),
+            end_slope: Slope::new(1, 1),
+        }];
+
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+
+            let mut previous_is_wall = None;
+            for column in row.min_column()..=row.max_column() {
+                let position = quadrant.transform(origin, row.depth, column);
+                let is_wall = blocks_sight(position);
+                let in_radius = row.depth * row.depth + column * column <= radius * radius;
+
+                if in_radius && (is_wall || row.is_symmetric(column)) {
+                    visible.insert(position);
+                }
+                if previous_is_wall == Some(true) && !is_wall {
+                    row.start_slope = Slope::of_tile(row.depth, column);
+                }
+                if previous_is_wall == Some(false) && is_wall {
+                    let mut next = row.next();
+                    next.end_slope = Slope::of_tile(row.depth, column);
+                    rows.push(next);
+                }
+                previous_is_wall = Some(is_wall);
+            }
+
+            if previous_is_wall == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+
+    visible
+}
+
+/// Bresenham line between both positions, true if nothing in between blocks
+/// sight.
+pub fn has_line_of_sight(
+    from: Position,
+    to: Position,
+    blocks_sight: impl Fn(Position) -> bool,
+) -> bool {
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let step_x = if from.x < to.x { 1 } else { -1 };
+    let step_y = if from.y < to.y { 1 } else { -1 };
+    let mut error = dx + dy;
+    let mut current = from;
+
+    while current != to {
+        if current != from && blocks_sight(current) {
+            return false;
+        }
+        let doubled_error = 2 * error;
+        if doubled_error >= dy {
+            error += dy;
+            current.x += step_x;
+        }
+        if doubled_error <= dx {
+            error += dx;
+            current.y += step_y;
+        }
+    }
+
+    true
+}
+
+#[derive(Clone, Copy)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    fn transform(self, origin: Position, depth: i64, column: i64) -> Position {
+        let (x, y) = match self {
+            Quadrant::North => (origin.x + column, origin.y + depth),
+            Quadrant::South => (origin.x + column, origin.y - depth),
+            Quadrant::East => (origin.x + depth, origin.y + column),
+            Quadrant::West => (origin.x - depth, origin.y + column),
+        };
+        Position { x, y }
+    }
+}
+
+/// Exact fraction, floats would break the symmetry on some tiles.
+#[derive(Clone, Copy)]
+struct Slope {
+    numerator: i64,
+    denominator: i64,
+}
+
+impl Slope {
+    fn new(numerator: i64, denominator: i64) -> Self {
+        Slope {
+            numerator,
+            denominator,
+        }
+    }
+
+    fn of_tile(depth: i64, column: i64) -> Self {
+        Slope::new(2 * column - 1, 2 * depth)
+    }
+}
+
+struct Row {
+    depth: i64,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    /// `depth * start_slope` rounded with ties going up.
+    fn min_column(&self) -> i64 {
+        let Slope {
+            numerator,
+            denominator,
+        } = self.start_slope;
+        (2 * self.depth * numerator + denominator).div_euclid(2 * denominator)
+    }
+
+    /// `depth * end_slope` rounded with ties going down.
+    fn max_column(&self) -> i64 {
+        let Slope {
+            numerator,
+            denominator,
+        } = self.end_slope;
+        -(denominator - 2 * self.depth * numerator).div_euclid(2 * denominator)
+    }
+
+    fn is_symmetric(&self, column: i64) -> bool {
+        column * self.start_slope.denominator >= self.depth * self.start_slope.numerator
+            && column * self.end_slope.denominator <= self.depth * self.end_slope.numerator
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start_slope: self.start_slope,
+            end_slope: self.end_slope,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walls drawn with `#`, anything else is floor.
+    fn walls(rows: &[&str]) -> HashSet<Position> {
+        let mut walls = HashSet::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, character) in row.chars().enumerate() {
+                if character == '#' {
+                    walls.insert(Position {
+                        x: x as i64,
+                        y: y as i64,
+                    });
+                }
+            }
+        }
+        walls
+    }
+
+    fn fov(walls: &HashSet<Position>, origin: (i64, i64), radius: i64) -> HashSet<Position> {
+        compute_fov(
+            Position {
+                x: origin.0,
+                y: origin.1,
+            },
+            radius,
+            |position| walls.contains(&position),
+        )
+    }
+
+    const PILLARS: [&str; 9] = [
+        "#############",
+        "#...........#",
+        "#.#...#..#..#",
+        "#.....#.....#",
+        "#..#.....#..#",
+        "#.......##..#",
+        "#.#..#......#",
+        "#...........#",
+        "#############",
+    ];
+
+    #[test]
+    fn sight_is_symmetric_between_floor_tiles() {
+        let walls = walls(&PILLARS);
+        let floors: Vec<Position> = (0..PILLARS[0].len() as i64)
+            .flat_map(|x| (0..PILLARS.len() as i64).map(move |y| Position { x, y }))
+            .filter(|position| !walls.contains(position))
+            .collect();
+        let views: Vec<_> = floors
+            .iter()
+            .map(|&floor| fov(&walls, (floor.x, floor.y), VIEW_RADIUS))
+            .collect();
+
+        for (a, a_view) in floors.iter().zip(views.iter()) {
+            for (b, b_view) in floors.iter().zip(views.iter()) {
+                assert_eq!(
+                    a_view.contains(b),
+                    b_view.contains(a),
+                    "{a:?} and {b:?} don't agree on seeing each other"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn walls_are_seen_but_hide_what_is_behind() {
+        let walls = walls(&[
+            ".....................",
+            ".....................",
+            "..........#..........",
+            ".....................",
+        ]);
+        let visible = fov(&walls, (10, 0), VIEW_RADIUS);
+
+        assert!(visible.contains(&Position { x: 10, y: 2 }));
+        assert!(!visible.contains(&Position { x: 10, y: 3 }));
+        assert!(visible.contains(&Position { x: 8, y: 3 }));
+    }
+
+    #[test]
+    fn sight_stops_at_the_radius() {
+        let visible = fov(&HashSet::new(), (0, 0), 3);
+
+        assert!(visible.contains(&Position { x: 0, y: 0 }));
+        assert!(visible.contains(&Position { x: 3, y: 0 }));
+        assert!(visible.contains(&Position { x: 2, y: -2 }));
+        assert!(!visible.contains(&Position { x: 4, y: 0 }));
+        assert!(!visible.contains(&Position { x: 3, y: 3 }));
+        assert!(visible
+            .iter()
+            .all(|position| position.x * position.x + position.y * position.y <= 9));
+    }
+
+    #[test]
+    fn line_of_sight_is_blocked_between_the_ends_only() {
+        let walls = walls(&["..#..", "....."]);
+        let blocks = |position| walls.contains(&position);
+        let at = |x, y| Position { x, y };
+
+        assert!(!has_line_of_sight(at(0, 0), at(4, 0), blocks));
+        assert!(has_line_of_sight(at(0, 1), at(4, 1), blocks));
+        assert!(has_line_of_sight(at(0, 0), at(2, 0), blocks));
+        assert!(has_line_of_sight(at(2, 0), at(4, 0), blocks));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::raw_loader::{GameData, GameDataHandle};
+
+use super::{
+    allies::{self, FOLLOW_RADIUS},
+    components::{EntityId, GameState, Position, Stairs},
+    game_world_from_state, generate_level, queue_level_events, EntityIdGenerator, Game, GameSeed,
+};
+
+/// States of the visited levels, the current one being owned by the `Game`.
+pub struct LevelStore {
+    pub current_depth: u32,
+    pub levels: HashMap<u32, GameState>,
+}
+
+impl LevelStore {
+    pub fn new() -> Self {
+        LevelStore {
+            current_depth: 0,
+            levels: HashMap::new(),
+        }
+    }
+}
+
+pub struct ChangeLevel {
+    pub entity_id: EntityId,
+    pub stairs: Stairs,
+}
+
+/// Moves `entity_id` from one level to another, at `position` if it has one
+/// there. Pending stairs and attack confirmations stay behind.
+fn move_entity(
+    entity_id: EntityId,
+    from: &mut GameState,
+    to: &mut GameState,
+    position: Option<Position>,
+) {
+    from.takingstairs.remove(&entity_id);
+    from.attackconfirmation.remove(&entity_id);
+    from.position.remove(&entity_id);
+    if let Some(position) = position {
+        to.position.insert(entity_id, position);
+    }
+    from.move_components(entity_id, to);
+}
+
+/// Items carried by `entity_id`.
+fn carried_by(state: &GameState, entity_id: EntityId) -> Vec<EntityId> {
+    state
+        .carriedby
+        .iter()
+        .filter(|(_, carried_by)| carried_by.0 == entity_id)
+        .map(|(&id, _)| id)
+        .collect()
+}
+
+/// Where an entity coming through `stairs` arrives on the level.
+fn arrival_position(state: &GameState, stairs: Stairs) -> Option<Position> {
+    let wanted = match stairs {
+        Stairs::Down => Stairs::Up,
+        Stairs::Up => Stairs::Down,
+    };
+    state
+        .stairs
+        .iter()
+        .find(|(_, other)| **other == wanted)
+        .and_then(|(&id, _)| state.get_position(id).copied())
+}
+
+pub fn change_level(
+    mut events: EventReader<ChangeLevel>,
+    mut world: ResMut<Game>,
+    mut levels: ResMut<LevelStore>,
+    mut id_generator: ResMut<EntityIdGenerator>,
+    seed: Res<GameSeed>,
+    data_asset: Res<Assets<GameData>>,
+    game_data_handle: Res<GameDataHandle>,
+) {
+    let game_data = data_asset
+        .get(&game_data_handle.0)
+        .expect("Failed to get game data");
+
+    for event in events.iter() {
+        // Monsters stay on their level
+        if world.state.get_player(event.entity_id).is_none() {
+            continue;
+        }
+
+        let current_depth = levels.current_depth;
+        let next_depth = match event.stairs {
+            Stairs::Down => current_depth + 1,
+            Stairs::Up if current_depth > 0 => current_depth - 1,
+            Stairs::Up => {
+                println!("There is no way out");
+                continue;
+            }
+        };
+
+        let (mut next_state, arrival) = match levels.levels.remove(&next_depth) {
+            Some(state) => {
+                let arrival = arrival_position(&state, event.stairs);
+                (state, arrival)
+            }
+            None => {
+                let (next_world, player_start) =
+                    generate_level(next_depth, seed.0, &mut id_generator, game_data);
+                (next_world.state, Some(player_start))
+            }
+        };
+
+        let carried = carried_by(&world.state, event.entity_id);
+        let followers = allies::followers_of(&world.state, event.entity_id);
+
+        move_entity(event.entity_id, &mut world.state, &mut next_state, arrival);
+        for id in carried {
+            move_entity(id, &mut world.state, &mut next_state, None);
+        }
+        // Allies without room next to the stairs stay behind, with their items
+        for id in followers {
+            let position = arrival
+                .and_then(|arrival| allies::free_spot_near(&next_state, arrival, FOLLOW_RADIUS));
+            if position.is_some() {
+                let carried = carried_by(&world.state, id);
+                move_entity(id, &mut world.state, &mut next_state, position);
+                for item_id in carried {
+                    move_entity(item_id, &mut world.state, &mut next_state, None);
+                }
+            }
+        }
+
+        let previous = std::mem::replace(&mut *world, game_world_from_state(next_state));
+        levels.levels.insert(current_depth, previous.state);
+        levels.current_depth = next_depth;
+        println!("Entering depth {next_depth}");
+
+        queue_level_events(&mut world);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+
+use bevy::prelude::Color;
+use rstar::RTree;
+
+use super::{
+    components::{EntityId, GameState, LightSource, Position, PositionTreeObject},
+    fov,
+};
+
+/// Below this brightness a tile is too dark to be seen.
+pub const MIN_VISIBLE_LIGHT: f32 = 0.1;
+
+/// Light received by every tile of a level, from all of its light sources.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightMap {
+    width: i64,
+    height: i64,
+    light: Vec<Color>,
+}
+
+impl LightMap {
+    pub fn new(width: i64, height: i64) -> Self {
+        LightMap {
+            width,
+            height,
+            light: vec![Color::BLACK; (width * height) as usize],
+        }
+    }
+
+    fn index(&self, position: Position) -> Option<usize> {
+        if position.x >= 0 && position.y >= 0 && position.x < self.width && position.y < self.height
+        {
+            Some((position.y * self.width + position.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Anything outside of the map is in the dark.
+    pub fn get(&self, position: Position) -> Color {
+        self.index(position)
+            .map(|index| self.light[index])
+            .unwrap_or(Color::BLACK)
+    }
+
+    pub fn brightness(&self, position: Position) -> f32 {
+        let light = self.get(position);
+        light.r().max(light.g()).max(light.b())
+    }
+
+    pub fn is_lit(&self, position: Position) -> bool {
+        self.brightness(position) >= MIN_VISIBLE_LIGHT
+    }
+
+    /// Lights add up, each channel being capped at full brightness.
+    fn add(&mut self, position: Position, color: Color, amount: f32) {
+        if let Some(index) = self.index(position) {
+            let light = self.light[index];
+            self.light[index] = Color::rgb(
+                (light.r() + color.r() * amount).min(1.0),
+                (light.g() + color.g() * amount).min(1.0),
+                (light.b() + color.b() * amount).min(1.0),
+            );
+        }
+    }
+}
+
+/// Lights the level from every `LightSource`, `light_position` telling where
+/// each of them is. Light fades linearly with the distance and is stopped by
+/// whatever blocks the sight.
+pub fn compute_light_map(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    light_position: impl Fn(EntityId) -> Option<Position>,
+) -> LightMap {
+    let mut light_map = match state.map() {
+        Some(map) => LightMap::new(map.width, map.height),
+        None => return LightMap::default(),
+    };
+
+    for (&entity_id, light) in state.lightsource.iter() {
+        let LightSource {
+            radius,
+            color,
+            intensity,
+        } = *light;
+        let origin = match light_position(entity_id) {
+            Some(origin) => origin,
+            None => continue,
+        };
+
+        for position in fov::field_of_view(state, spatial_position, origin, radius) {
+            let dx = (position.x - origin.x) as f32;
+            let dy = (position.y - origin.y) as f32;
+            let falloff = 1.0 - (dx * dx + dy * dy).sqrt() / (radius + 1) as f32;
+            if falloff > 0.0 {
+                light_map.add(position, color, intensity * falloff);
+            }
+        }
+    }
+
+    light_map
+}
+
+/// Where the light of an entity shines from: its own position, or the one of
+/// the entity carrying it.
+pub fn light_position(state: &GameState, entity_id: EntityId) -> Option<Position> {
+    light_position_with(
+        |id| state.get_position(id).copied(),
+        |id| state.get_carriedby(id).map(|carrier| carrier.0),
+        entity_id,
+    )
+}
+
+/// Same as `light_position`, looking positions and carriers up with the
+/// given functions, such as the ones of a state being changed by an action.
+pub fn light_position_with(
+    position: impl Fn(EntityId) -> Option<Position>,
+    carrier: impl Fn(EntityId) -> Option<EntityId>,
+    entity_id: EntityId,
+) -> Option<Position> {
+    position(entity_id).or_else(|| carrier(entity_id).and_then(&position))
+}
+
+/// Tiles of the field of view that are bright enough to be seen. Whatever
+/// is right next to `origin` can always be made out, even in the dark.
+pub fn visible_in_light(
+    field_of_view: HashSet<Position>,
+    light_map: &LightMap,
+    origin: Position,
+) -> HashSet<Position> {
+    field_of_view
+        .into_iter()
+        .filter(|&position| {
+            light_map.is_lit(position)
+                || ((position.x - origin.x).abs() <= 1 && (position.y - origin.y).abs() <= 1)
+        })
+        .collect()
+}
@@ -0,0 +1,211 @@
+use bevy::prelude::Color;
+use serde::{Deserialize, Serialize};
+
+use super::components::{EntityId, GameState, Glyph, Position};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TileType {
+    Floor,
+    Wall,
+    DoorFrame,
+    Road,
+    Rubble,
+    ShallowWater,
+    DeepWater,
+    Lava,
+    Acid,
+}
+
+/// What happens to an entity ending its move on a tile.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Hazard {
+    Burning,
+    Corroding,
+    Drowning,
+}
+
+impl Hazard {
+    pub fn damage(self) -> i64 {
+        match self {
+            Hazard::Burning => 20,
+            Hazard::Corroding => 10,
+            Hazard::Drowning => 5,
+        }
+    }
+}
+
+impl TileType {
+    pub fn is_blocking(self) -> bool {
+        matches!(self, TileType::Wall)
+    }
+
+    pub fn blocks_sight(self) -> bool {
+        matches!(self, TileType::Wall)
+    }
+
+    /// Percentage applied to the cost of a move ending on the tile.
+    pub fn move_cost(self) -> u32 {
+        match self {
+            TileType::Road => 75,
+            TileType::Rubble => 150,
+            TileType::ShallowWater | TileType::DeepWater => 200,
+            _ => 100,
+        }
+    }
+
+    pub fn hazard(self) -> Option<Hazard> {
+        match self {
+            TileType::Lava => Some(Hazard::Burning),
+            TileType::Acid => Some(Hazard::Corroding),
+            TileType::DeepWater => Some(Hazard::Drowning),
+            _ => None,
+        }
+    }
+
+    /// Damage a tile can take before being dug through, `None` if it can't be.
+    pub fn hit_points(self) -> Option<i64> {
+        match self {
+            TileType::Wall => Some(40),
+            TileType::Rubble => Some(15),
+            _ => None,
+        }
+    }
+
+    pub fn glyph(self) -> Glyph {
+        let (character, color) = match self {
+            TileType::Floor => ('.', Color::rgb(0.3, 0.3, 0.3)),
+            TileType::Wall => ('#', Color::rgb(0.6, 0.6, 0.6)),
+            TileType::DoorFrame => ('.', Color::rgb(0.5, 0.35, 0.2)),
+            TileType::Road => ('.', Color::rgb(0.6, 0.5, 0.3)),
+            TileType::Rubble => (':', Color::rgb(0.5, 0.45, 0.4)),
+            TileType::ShallowWater => ('~', Color::rgb(0.3, 0.5, 0.9)),
+            TileType::DeepWater => ('~', Color::rgb(0.1, 0.15, 0.7)),
+            TileType::Lava => ('~', Color::rgb(1.0, 0.35, 0.0)),
+            TileType::Acid => ('~', Color::rgb(0.5, 0.9, 0.1)),
+        };
+        Glyph { character, color }
+    }
+}
+
+/// Grid of tiles for a level. Lives on a single entity of the `GameState`
+/// so that it is saved and processed like any other component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileMap {
+    pub width: i64,
+    pub height: i64,
+    tiles: Vec<TileType>,
+    /// Tiles the player has already seen
+    revealed: Vec<bool>,
+    /// Damage taken by the tiles being dug
+    damage: Vec<i64>,
+}
+
+impl TileMap {
+    pub fn new(width: i64, height: i64, fill: TileType) -> Self {
+        TileMap {
+            width,
+            height,
+            tiles: vec![fill; (width * height) as usize],
+            revealed: vec![false; (width * height) as usize],
+            damage: vec![0; (width * height) as usize],
+        }
+    }
+
+    pub fn in_bounds(&self, position: Position) -> bool {
+        position.x >= 0 && position.y >= 0 && position.x < self.width && position.y < self.height
+    }
+
+    fn index(&self, position: Position) -> Option<usize> {
+        if self.in_bounds(position) {
+            Some((position.y * self.width + position.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Anything outside of the map is considered a wall.
+    pub fn get(&self, position: Position) -> TileType {
+        self.index(position)
+            .map(|index| self.tiles[index])
+            .unwrap_or(TileType::Wall)
+    }
+
+    pub fn set(&mut self, position: Position, tile: TileType) {
+        if let Some(index) = self.index(position) {
+            self.tiles[index] = tile;
+        }
+    }
+
+    pub fn is_revealed(&self, position: Position) -> bool {
+        self.index(position)
+            .map(|index| self.revealed[index])
+            .unwrap_or(false)
+    }
+
+    pub fn reveal(&mut self, position: Position) {
+        if let Some(index) = self.index(position) {
+            self.revealed[index] = true;
+        }
+    }
+
+    /// Damages a diggable tile, which turns into floor once it runs out of hit
+    /// points. The border of the map can't be dug so that it stays closed.
+    /// Returns whether the tile was destroyed.
+    pub fn dig(&mut self, position: Position, damage: i64) -> bool {
+        if position.x == 0
+            || position.y == 0
+            || position.x == self.width - 1
+            || position.y == self.height - 1
+        {
+            return false;
+        }
+
+        if let (Some(index), Some(hit_points)) =
+            (self.index(position), self.get(position).hit_points())
+        {
+            self.damage[index] += damage;
+            if self.damage[index] >= hit_points {
+                self.damage[index] = 0;
+                self.tiles[index] = TileType::Floor;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Tiles that differ in `other`, with their new type.
+    pub fn changed_tiles(&self, other: &TileMap) -> Vec<(Position, TileType)> {
+        self.tiles()
+            .filter(|&(position, tile)| other.get(position) != tile)
+            .map(|(position, _)| (position, other.get(position)))
+            .collect()
+    }
+
+    pub fn is_blocking(&self, position: Position) -> bool {
+        self.get(position).is_blocking()
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = (Position, TileType)> + '_ {
+        self.tiles.iter().enumerate().map(|(index, &tile)| {
+            let index = index as i64;
+            (
+                Position {
+                    x: index % self.width,
+                    y: index / self.width,
+                },
+                tile,
+            )
+        })
+    }
+}
+
+impl GameState {
+    /// The map of the level, held by a single entity.
+    pub fn map(&self) -> Option<&TileMap> {
+        self.tilemap.values().next()
+    }
+
+    pub fn map_id(&self) -> Option<EntityId> {
+        self.tilemap.keys().next().copied()
+    }
+}
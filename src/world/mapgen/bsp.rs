@@ -0,0 +1,87 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::world::map::{TileMap, TileType};
+
+use super::{carve_corridor, carve_room, mark_doorways, GeneratedMap, MapGenerator, Rect};
+
+const MIN_LEAF_SIZE: i64 = 10;
+const MIN_ROOM_SIZE: i64 = 4;
+
+/// Binary space partitioning: the map is split in two until the parts are
+/// small enough, a room is dug in each leaf and siblings are linked together.
+pub struct BspRooms;
+
+impl MapGenerator for BspRooms {
+    fn name(&self) -> &'static str {
+        "bsp rooms"
+    }
+
+    fn generate(&self, width: i64, height: i64, rng: &mut StdRng) -> GeneratedMap {
+        let mut map = TileMap::new(width, height, TileType::Wall);
+        let mut rooms = Vec::new();
+
+        split(
+            &mut map,
+            Rect::new(1, 1, width - 2, height - 2),
+            &mut rooms,
+            rng,
+        );
+
+        mark_doorways(&mut map, &rooms);
+
+        let player_start = rooms[0].center();
+        let spawn_regions = rooms.iter().map(Rect::positions).collect();
+
+        GeneratedMap::connected(map, player_start, spawn_regions)
+    }
+}
+
+/// Digs the rooms of `area` and returns one of them, to be linked with the
+/// sibling area.
+fn split(map: &mut TileMap, area: Rect, rooms: &mut Vec<Rect>, rng: &mut StdRng) -> Rect {
+    let can_split_vertically = area.width() >= MIN_LEAF_SIZE * 2;
+    let can_split_horizontally = area.height() >= MIN_LEAF_SIZE * 2;
+
+    let halves = match (can_split_vertically, can_split_horizontally) {
+        (false, false) => None,
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        (true, true) => Some(rng.gen_bool(0.5)),
+    }
+    .map(|vertically| {
+        if vertically {
+            let at = rng.gen_range(MIN_LEAF_SIZE..=area.width() - MIN_LEAF_SIZE);
+            (
+                Rect::new(area.x1, area.y1, at, area.height()),
+                Rect::new(area.x1 + at, area.y1, area.width() - at, area.height()),
+            )
+        } else {
+            let at = rng.gen_range(MIN_LEAF_SIZE..=area.height() - MIN_LEAF_SIZE);
+            (
+                Rect::new(area.x1, area.y1, area.width(), at),
+                Rect::new(area.x1, area.y1 + at, area.width(), area.height() - at),
+            )
+        }
+    });
+
+    match halves {
+        Some((first, second)) => {
+            let first_room = split(map, first, rooms, rng);
+            let second_room = split(map, second, rooms, rng);
+            carve_corridor(map, first_room.center(), second_room.center(), rng);
+            first_room
+        }
+        None => {
+            // Leave a wall between the room and the borders of its leaf
+            let w = rng.gen_range(MIN_ROOM_SIZE..=area.width() - 2);
+            let h = rng.gen_range(MIN_ROOM_SIZE..=area.height() - 2);
+            let x = rng.gen_range(area.x1 + 1..=area.x2 - 1 - w);
+            let y = rng.gen_range(area.y1 + 1..=area.y2 - 1 - h);
+            let room = Rect::new(x, y, w, h);
+
+            carve_room(map, &room);
+            rooms.push(room);
+            room
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::world::{
+    components::Position,
+    map::{TileMap, TileType},
+};
+
+use super::{chunk_regions, closest_floor, seal_borders, GeneratedMap, MapGenerator};
+
+const INITIAL_WALL_CHANCE: f64 = 0.45;
+const ITERATIONS: usize = 5;
+
+/// Cellular automata caves: random noise smoothed until walls gather into
+/// cave walls.
+pub struct CellularCaves;
+
+impl MapGenerator for CellularCaves {
+    fn name(&self) -> &'static str {
+        "cellular caves"
+    }
+
+    fn generate(&self, width: i64, height: i64, rng: &mut StdRng) -> GeneratedMap {
+        let mut map = TileMap::new(width, height, TileType::Floor);
+        for x in 0..width {
+            for y in 0..height {
+                if rng.gen_bool(INITIAL_WALL_CHANCE) {
+                    map.set(Position { x, y }, TileType::Wall);
+                }
+            }
+        }
+        seal_borders(&mut map);
+
+        for _ in 0..ITERATIONS {
+            let mut next = map.clone();
+            for (position, _) in map.tiles() {
+                let walls = count_walls_around(&map, position);
+                if walls > 4 || walls == 0 {
+                    next.set(position, TileType::Wall);
+                } else {
+                    next.set(position, TileType::Floor);
+                }
+            }
+            map = next;
+            seal_borders(&mut map);
+        }
+
+        let center = Position {
+            x: width / 2,
+            y: height / 2,
+        };
+        // Smoothing can fill the whole map, the player then starts in a tiny
+        // cave carved in the center
+        let player_start = match closest_floor(&map, center) {
+            Some(position) => position,
+            None => {
+                map.set(center, TileType::Floor);
+                center
+            }
+        };
+        let spawn_regions = chunk_regions(&map);
+
+        GeneratedMap::connected(map, player_start, spawn_regions)
+    }
+}
+
+/// Walls among the 8 surrounding tiles, the map border counting as walls.
+fn count_walls_around(map: &TileMap, position: Position) -> usize {
+    let mut walls = 0;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if (dx, dy) != (0, 0)
+                && map.is_blocking(Position {
+                    x: position.x + dx,
+                    y: position.y + dy,
+                })
+            {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
@@ -0,0 +1,63 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::world::{
+    components::Position,
+    map::{TileMap, TileType},
+};
+
+use super::{chunk_regions, seal_borders, GeneratedMap, MapGenerator};
+
+const FLOOR_RATIO: f64 = 0.4;
+const WALKER_LIFETIME: usize = 400;
+
+/// Drunkard's walk: walkers wander randomly from the center of the map,
+/// digging tunnels until enough of the map is floor.
+pub struct DrunkardsWalk;
+
+impl MapGenerator for DrunkardsWalk {
+    fn name(&self) -> &'static str {
+        "drunkard's walk"
+    }
+
+    fn generate(&self, width: i64, height: i64, rng: &mut StdRng) -> GeneratedMap {
+        let mut map = TileMap::new(width, height, TileType::Wall);
+        let player_start = Position {
+            x: width / 2,
+            y: height / 2,
+        };
+        map.set(player_start, TileType::Floor);
+
+        let wanted_floor = ((width * height) as f64 * FLOOR_RATIO) as usize;
+        let mut floor = 1;
+
+        while floor < wanted_floor {
+            // Every walker starts from already dug floor so tunnels stay linked
+            let mut walker = player_start;
+            for _ in 0..WALKER_LIFETIME {
+                if map.get(walker) == TileType::Wall {
+                    floor += 1;
+                }
+                map.set(walker, TileType::Floor);
+
+                let (dx, dy) = match rng.gen_range(0..4) {
+                    0 => (1, 0),
+                    1 => (-1, 0),
+                    2 => (0, 1),
+                    _ => (0, -1),
+                };
+                let next = Position {
+                    x: walker.x + dx,
+                    y: walker.y + dy,
+                };
+                if next.x > 0 && next.y > 0 && next.x < width - 1 && next.y < height - 1 {
+                    walker = next;
+                }
+            }
+        }
+        seal_borders(&mut map);
+
+        let spawn_regions = chunk_regions(&map);
+
+        GeneratedMap::connected(map, player_start, spawn_regions)
+    }
+}
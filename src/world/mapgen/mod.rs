@@ -0,0 +1,371 @@
+pub mod bsp;
+pub mod caves;
+pub mod drunkard;
+pub mod prefab;
+pub mod rooms;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+use super::{
+    components::Position,
+    map::{TileMap, TileType},
+};
+
+/// Size of the chunks used to split open maps (caves, tunnels) into spawn regions.
+const REGION_SIZE: i64 = 16;
+/// Terrain features are kept away from the start of the level.
+const SAFE_START_DISTANCE: i64 = 6;
+const MAX_POOLS: usize = 6;
+
+/// Builds the layout of a level. Generators only carve tiles, placing the
+/// player, monsters and items is done from the returned `GeneratedMap`.
+pub trait MapGenerator {
+    fn name(&self) -> &'static str;
+
+    fn generate(&self, width: i64, height: i64, rng: &mut StdRng) -> GeneratedMap;
+}
+
+pub fn random_generator(rng: &mut StdRng) -> Box<dyn MapGenerator> {
+    match rng.gen_range(0..4) {
+        0 => Box::new(rooms::RoomsAndCorridors),
+        1 => Box::new(caves::CellularCaves),
+        2 => Box::new(drunkard::DrunkardsWalk),
+        _ => Box::new(bsp::BspRooms),
+    }
+}
+
+pub struct GeneratedMap {
+    pub map: TileMap,
+    pub player_start: Position,
+    /// Floor positions grouped by area (rooms, cave chunks...), the one
+    /// holding `player_start` is left out.
+    pub spawn_regions: Vec<Vec<Position>>,
+    /// Door frames that always get a door, such as the ones of prefabs.
+    pub forced_doors: Vec<Position>,
+    /// Monsters and items requested by prefabs, by template id.
+    pub placeholders: Vec<(Position, String)>,
+}
+
+impl GeneratedMap {
+    /// Every floor tile that cannot be reached from `player_start` is filled
+    /// back with walls, so anything placed on a spawn region is reachable.
+    pub fn connected(
+        mut map: TileMap,
+        player_start: Position,
+        spawn_regions: Vec<Vec<Position>>,
+    ) -> Self {
+        let reachable = reachable_from(&map, player_start);
+        let unreachable: Vec<_> = map
+            .tiles()
+            .filter(|&(position, tile)| !tile.is_blocking() && !reachable.contains(&position))
+            .map(|(position, _)| position)
+            .collect();
+        for position in unreachable {
+            map.set(position, TileType::Wall);
+        }
+
+        let spawn_regions = spawn_regions
+            .into_iter()
+            .filter(|region| !region.contains(&player_start))
+            .map(|region| {
+                region
+                    .into_iter()
+                    .filter(|position| reachable.contains(position))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|region| !region.is_empty())
+            .collect();
+
+        GeneratedMap {
+            map,
+            player_start,
+            spawn_regions,
+            forced_doors: Vec::new(),
+            placeholders: Vec::new(),
+        }
+    }
+}
+
+/// Axis aligned rectangle of floor, `x2` and `y2` being exclusive.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Rect {
+    pub x1: i64,
+    pub y1: i64,
+    pub x2: i64,
+    pub y2: i64,
+}
+
+impl Rect {
+    pub fn new(x: i64, y: i64, width: i64, height: i64) -> Self {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + width,
+            y2: y + height,
+        }
+    }
+
+    pub fn width(&self) -> i64 {
+        self.x2 - self.x1
+    }
+
+    pub fn height(&self) -> i64 {
+        self.y2 - self.y1
+    }
+
+    /// Also true when the rectangles are only separated by a single wall.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x1 <= other.x2 && self.x2 >= other.x1 && self.y1 <= other.y2 && self.y2 >= other.y1
+    }
+
+    pub fn center(&self) -> Position {
+        Position {
+            x: (self.x1 + self.x2) / 2,
+            y: (self.y1 + self.y2) / 2,
+        }
+    }
+
+    pub fn positions(&self) -> Vec<Position> {
+        (self.x1..self.x2)
+            .flat_map(|x| (self.y1..self.y2).map(move |y| Position { x, y }))
+            .collect()
+    }
+}
+
+fn carve_room(map: &mut TileMap, room: &Rect) {
+    for position in room.positions() {
+        map.set(position, TileType::Floor);
+    }
+}
+
+/// Corridors are roads, except where they cross an already dug room.
+fn carve_tunnel_tile(map: &mut TileMap, position: Position) {
+    if map.get(position) == TileType::Wall {
+        map.set(position, TileType::Road);
+    }
+}
+
+fn carve_horizontal_tunnel(map: &mut TileMap, x1: i64, x2: i64, y: i64) {
+    for x in x1.min(x2)..=x1.max(x2) {
+        carve_tunnel_tile(map, Position { x, y });
+    }
+}
+
+fn carve_vertical_tunnel(map: &mut TileMap, y1: i64, y2: i64, x: i64) {
+    for y in y1.min(y2)..=y1.max(y2) {
+        carve_tunnel_tile(map, Position { x, y });
+    }
+}
+
+fn carve_corridor(map: &mut TileMap, from: Position, to: Position, rng: &mut StdRng) {
+    if rng.gen_bool(0.5) {
+        carve_horizontal_tunnel(map, from.x, to.x, from.y);
+        carve_vertical_tunnel(map, from.y, to.y, to.x);
+    } else {
+        carve_vertical_tunnel(map, from.y, to.y, from.x);
+        carve_horizontal_tunnel(map, from.x, to.x, to.y);
+    }
+}
+
+/// Turns the corridor tiles entering a room through a wall into door frames.
+fn mark_doorways(map: &mut TileMap, rooms: &[Rect]) {
+    for room in rooms {
+        let horizontal_sides = (room.x1..room.x2)
+            .flat_map(|x| [(x, room.y1 - 1), (x, room.y2)])
+            .map(|(x, y)| (Position { x, y }, (1, 0)));
+        let vertical_sides = (room.y1..room.y2)
+            .flat_map(|y| [(room.x1 - 1, y), (room.x2, y)])
+            .map(|(x, y)| (Position { x, y }, (0, 1)));
+
+        for (position, (dx, dy)) in horizontal_sides.chain(vertical_sides) {
+            let before = Position {
+                x: position.x - dx,
+                y: position.y - dy,
+            };
+            let after = Position {
+                x: position.x + dx,
+                y: position.y + dy,
+            };
+            if map.get(position) == TileType::Road
+                && map.get(before) == TileType::Wall
+                && map.get(after) == TileType::Wall
+            {
+                map.set(position, TileType::DoorFrame);
+            }
+        }
+    }
+}
+
+/// Scatters pools of water, rubble and, deeper in the dungeon, lava and acid
+/// over the floor of a generated map.
+pub fn add_terrain_features(generated: &mut GeneratedMap, depth: u32, rng: &mut StdRng) {
+    let mut features = vec![
+        TileType::ShallowWater,
+        TileType::DeepWater,
+        TileType::Rubble,
+    ];
+    if depth >= 2 {
+        features.push(TileType::Acid);
+    }
+    if depth >= 4 {
+        features.push(TileType::Lava);
+    }
+
+    let floor: Vec<_> = generated
+        .map
+        .tiles()
+        .filter(|&(position, tile)| {
+            tile == TileType::Floor
+                && ((position.x - generated.player_start.x).abs() > SAFE_START_DISTANCE
+                    || (position.y - generated.player_start.y).abs() > SAFE_START_DISTANCE)
+        })
+        .map(|(position, _)| position)
+        .collect();
+
+    for _ in 0..rng.gen_range(0..=MAX_POOLS) {
+        if let (Some(&center), Some(&feature)) = (floor.choose(rng), features.choose(rng)) {
+            let radius = rng.gen_range(1..=3);
+            let mut map = generated.map.clone();
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    let position = Position {
+                        x: center.x + dx,
+                        y: center.y + dy,
+                    };
+                    if dx * dx + dy * dy <= radius * radius && map.get(position) == TileType::Floor
+                    {
+                        map.set(position, feature);
+                    }
+                }
+            }
+
+            // A hazard across a chokepoint would have to be crossed
+            if feature.hazard().is_none()
+                || keeps_safe_paths(&generated.map, &map, generated.player_start)
+            {
+                generated.map = map;
+            }
+        }
+    }
+
+    // Nothing is put in a pool of lava, acid or deep water
+    let map = &generated.map;
+    for region in generated.spawn_regions.iter_mut() {
+        region.retain(|&position| map.get(position).hazard().is_none());
+    }
+    generated.spawn_regions.retain(|region| !region.is_empty());
+}
+
+/// Whether every tile that could be reached from `start` without crossing a
+/// hazard still can once the map is changed to `after`, except for the tiles
+/// that became hazards themselves.
+fn keeps_safe_paths(before: &TileMap, after: &TileMap, start: Position) -> bool {
+    let safe = |tile: TileType| !tile.is_blocking() && tile.hazard().is_none();
+    let reachable_after = reachable_over(after, start, safe);
+    reachable_over(before, start, safe)
+        .into_iter()
+        .filter(|&position| safe(after.get(position)))
+        .all(|position| reachable_after.contains(&position))
+}
+
+/// Keeps a one tile wide wall around the map.
+fn seal_borders(map: &mut TileMap) {
+    for x in 0..map.width {
+        map.set(Position { x, y: 0 }, TileType::Wall);
+        map.set(
+            Position {
+                x,
+                y: map.height - 1,
+            },
+            TileType::Wall,
+        );
+    }
+    for y in 0..map.height {
+        map.set(Position { x: 0, y }, TileType::Wall);
+        map.set(
+            Position {
+                x: map.width - 1,
+                y,
+            },
+            TileType::Wall,
+        );
+    }
+}
+
+fn neighbours(position: Position) -> [Position; 4] {
+    [
+        Position {
+            x: position.x + 1,
+            y: position.y,
+        },
+        Position {
+            x: position.x - 1,
+            y: position.y,
+        },
+        Position {
+            x: position.x,
+            y: position.y + 1,
+        },
+        Position {
+            x: position.x,
+            y: position.y - 1,
+        },
+    ]
+}
+
+fn reachable_from(map: &TileMap, start: Position) -> HashSet<Position> {
+    reachable_over(map, start, |tile| !tile.is_blocking())
+}
+
+/// Tiles reached from `start` by only stepping on `walkable` tiles.
+fn reachable_over(
+    map: &TileMap,
+    start: Position,
+    walkable: impl Fn(TileType) -> bool,
+) -> HashSet<Position> {
+    let mut reachable = HashSet::new();
+    let mut open = VecDeque::new();
+
+    if walkable(map.get(start)) {
+        reachable.insert(start);
+        open.push_back(start);
+    }
+
+    while let Some(position) = open.pop_front() {
+        for next in neighbours(position) {
+            if walkable(map.get(next)) && reachable.insert(next) {
+                open.push_back(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// The floor tile closest to `target`, used to start the player on open maps.
+fn closest_floor(map: &TileMap, target: Position) -> Option<Position> {
+    map.tiles()
+        .filter(|(_, tile)| !tile.is_blocking())
+        .map(|(position, _)| position)
+        .min_by_key(|position| (position.x - target.x).pow(2) + (position.y - target.y).pow(2))
+}
+
+/// Splits the floor of a map without rooms into square chunks.
+fn chunk_regions(map: &TileMap) -> Vec<Vec<Position>> {
+    let mut regions: HashMap<(i64, i64), Vec<Position>> = HashMap::new();
+    for (position, tile) in map.tiles() {
+        if !tile.is_blocking() {
+            regions
+                .entry((position.x / REGION_SIZE, position.y / REGION_SIZE))
+                .or_default()
+                .push(position);
+        }
+    }
+
+    let mut regions: Vec<_> = regions.into_iter().collect();
+    // HashMap order is random, keep the generation reproducible from the seed
+    regions.sort_by_key(|(chunk, _)| *chunk);
+    regions.into_iter().map(|(_, region)| region).collect()
+}
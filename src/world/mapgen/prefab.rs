@@ -0,0 +1,136 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    raw_loader::{PrefabCell, PrefabTemplate},
+    world::{components::Position, map::TileType},
+};
+
+use super::GeneratedMap;
+
+const MAX_PREFABS: usize = 2;
+const PLACEMENT_ATTEMPTS: usize = 100;
+
+/// Rotation and mirroring applied to a prefab when stamping it.
+#[derive(Debug, Clone, Copy)]
+struct Orientation {
+    quarter_turns: u8,
+    mirrored: bool,
+}
+
+impl Orientation {
+    fn random(rng: &mut StdRng) -> Self {
+        Orientation {
+            quarter_turns: rng.gen_range(0..4),
+            mirrored: rng.gen_bool(0.5),
+        }
+    }
+
+    /// Size of the prefab once oriented.
+    fn size(self, prefab: &PrefabTemplate) -> (i64, i64) {
+        if self.quarter_turns % 2 == 0 {
+            (prefab.width, prefab.height)
+        } else {
+            (prefab.height, prefab.width)
+        }
+    }
+
+    /// Cell of the prefab drawn at (`x`, `y`) once oriented, `y` going down.
+    fn cell(self, prefab: &PrefabTemplate, x: i64, y: i64) -> &PrefabCell {
+        let (width, height) = self.size(prefab);
+        let x = if self.mirrored { width - 1 - x } else { x };
+        let (x, y) = match self.quarter_turns {
+            0 => (x, y),
+            1 => (y, width - 1 - x),
+            2 => (width - 1 - x, height - 1 - y),
+            _ => (height - 1 - y, x),
+        };
+        prefab.cell(x, y)
+    }
+}
+
+/// Stamps a few prefabs in open areas of the map. A prefab is only put where
+/// it is surrounded by floor, so the rest of the level stays connected and
+/// its doors always open on walkable tiles.
+pub fn place_prefabs(generated: &mut GeneratedMap, prefabs: &[&PrefabTemplate], rng: &mut StdRng) {
+    if prefabs.is_empty() {
+        return;
+    }
+
+    let mut placed = 0;
+    for _ in 0..PLACEMENT_ATTEMPTS {
+        if placed >= MAX_PREFABS {
+            break;
+        }
+
+        let prefab = prefabs[rng.gen_range(0..prefabs.len())];
+        let orientation = Orientation::random(rng);
+        let (width, height) = orientation.size(prefab);
+        if width + 2 >= generated.map.width || height + 2 >= generated.map.height {
+            continue;
+        }
+        let origin = Position {
+            x: rng.gen_range(1..generated.map.width - width - 1),
+            y: rng.gen_range(1..generated.map.height - height - 1),
+        };
+
+        if fits(generated, origin, width, height) {
+            stamp(generated, prefab, orientation, origin);
+            placed += 1;
+        }
+    }
+}
+
+/// The footprint and the ring around it must be plain floor, away from the
+/// start of the level.
+fn fits(generated: &GeneratedMap, origin: Position, width: i64, height: i64) -> bool {
+    for x in origin.x - 1..=origin.x + width {
+        for y in origin.y - 1..=origin.y + height {
+            let position = Position { x, y };
+            if generated.map.get(position) != TileType::Floor || position == generated.player_start
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn stamp(
+    generated: &mut GeneratedMap,
+    prefab: &PrefabTemplate,
+    orientation: Orientation,
+    origin: Position,
+) {
+    let (width, height) = orientation.size(prefab);
+
+    for x in 0..width {
+        for y in 0..height {
+            // Rows are drawn from the top, the map y axis goes up
+            let position = Position {
+                x: origin.x + x,
+                y: origin.y + height - 1 - y,
+            };
+            match orientation.cell(prefab, x, y) {
+                PrefabCell::Tile(tile) => generated.map.set(position, *tile),
+                PrefabCell::Door => {
+                    generated.map.set(position, TileType::DoorFrame);
+                    generated.forced_doors.push(position);
+                }
+                PrefabCell::Spawn(template_id) => {
+                    generated.map.set(position, TileType::Floor);
+                    generated.placeholders.push((position, template_id.clone()));
+                }
+            }
+        }
+    }
+
+    // Prefab contents are given by the raws, nothing else spawns in there
+    for region in generated.spawn_regions.iter_mut() {
+        region.retain(|position| {
+            position.x < origin.x
+                || position.x >= origin.x + width
+                || position.y < origin.y
+                || position.y >= origin.y + height
+        });
+    }
+}
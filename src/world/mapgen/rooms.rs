@@ -0,0 +1,51 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::world::map::{TileMap, TileType};
+
+use super::{carve_corridor, carve_room, mark_doorways, GeneratedMap, MapGenerator, Rect};
+
+const MAX_ROOMS: usize = 30;
+const MIN_SIZE: i64 = 6;
+const MAX_SIZE: i64 = 12;
+
+/// Classic rooms and corridors: random non overlapping rooms, each one linked
+/// to the previous by an L shaped corridor.
+pub struct RoomsAndCorridors;
+
+impl MapGenerator for RoomsAndCorridors {
+    fn name(&self) -> &'static str {
+        "rooms and corridors"
+    }
+
+    fn generate(&self, width: i64, height: i64, rng: &mut StdRng) -> GeneratedMap {
+        let mut map = TileMap::new(width, height, TileType::Wall);
+        let mut rooms: Vec<Rect> = Vec::new();
+
+        for _ in 0..MAX_ROOMS {
+            let w = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+            let h = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+            let x = rng.gen_range(1..width - w - 1);
+            let y = rng.gen_range(1..height - h - 1);
+            let room = Rect::new(x, y, w, h);
+
+            if rooms.iter().any(|other| room.intersects(other)) {
+                continue;
+            }
+
+            carve_room(&mut map, &room);
+
+            if let Some(previous) = rooms.last() {
+                carve_corridor(&mut map, previous.center(), room.center(), rng);
+            }
+
+            rooms.push(room);
+        }
+
+        mark_doorways(&mut map, &rooms);
+
+        let player_start = rooms[0].center();
+        let spawn_regions = rooms.iter().map(Rect::positions).collect();
+
+        GeneratedMap::connected(map, player_start, spawn_regions)
+    }
+}
@@ -1,15 +1,31 @@
 pub mod actions;
+pub mod ai;
+pub mod allies;
 pub mod components;
+pub mod dijkstra;
+pub mod fov;
+pub mod levels;
+pub mod lighting;
+pub mod map;
+pub mod mapgen;
+pub mod morale;
+pub mod pack;
+pub mod pathfinding;
+pub mod perception;
 mod rules;
+pub mod spawns;
+pub mod stats;
+pub mod utility;
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
+use rand::{prelude::*, rngs::StdRng};
 use rstar::RTree;
 
 use crate::{
-    raw_loader::{GameData, GameDataHandle},
+    raw_loader::{GameData, GameDataHandle, SpawnEntry, SpawnKind},
     save::*,
     AppState,
 };
@@ -17,26 +33,54 @@ use crate::{
 use self::{
     actions::*,
     components::{
-        Action, EntityId, FutureState, GameState, GameWorld, Glyph, Name, Position,
-        PositionTreeObject,
+        Action, ActionStatus, Alertness, Awareness, Door, DoorState, EntityId, FactionTable,
+        FutureState, GameState, GameWorld, Glyph, Key, Name, Position, PositionTreeObject,
+        RuleStatus, Stairs,
     },
+    dijkstra::DijkstraMaps,
+    levels::{ChangeLevel, LevelStore},
+    lighting::LightMap,
+    map::{TileMap, TileType},
+    mapgen::GeneratedMap,
+    utility::UtilityTrace,
 };
 
+const MAP_WIDTH: i64 = 80;
+const MAP_HEIGHT: i64 = 50;
+const MAX_MONSTER_GROUPS_PER_REGION: usize = 2;
+const MAX_ITEMS_PER_REGION: usize = 2;
+const TRAP_CHANCE: f64 = 0.3;
+const SLEEPING_CHANCE: f64 = 0.4;
+const DOOR_CHANCE: f64 = 0.7;
+const LOCKED_DOOR_CHANCE: f64 = 0.1;
+
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(EntityIdGenerator::new())
+            .init_resource::<DijkstraMaps>()
+            .init_resource::<UtilityTrace>()
+            .add_event::<ChangeLevel>()
             .add_enter_system(AppState::GenerateWorld, spawn_world)
             .add_enter_system(AppState::LoadWorld, load_world)
-            .add_exit_system(AppState::GenerateWorld, spawn_potion)
-            .add_exit_system(AppState::GenerateWorld, spawn_orcs)
-            .add_exit_system(AppState::GenerateWorld, spawn_player);
+            .add_system(levels::change_level.run_in_state(AppState::InGame));
     }
 }
 
 pub type Game = GameWorld<ActionType, GameEvent>;
 
+type Rule = fn(
+    &Action,
+    &GameState,
+    &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>);
+type ActionCallback = fn(&mut VecDeque<GameEvent>, &Action, &GameState, &RTree<PositionTreeObject>);
+type StateCallback = fn(&mut VecDeque<GameEvent>, &GameState, &RTree<PositionTreeObject>);
+
+/// Seed of the current game, every level is generated from it.
+pub struct GameSeed(pub u64);
+
 pub enum GameEvent {
     Spawned {
         entity_id: EntityId,
@@ -59,39 +103,471 @@ pub enum GameEvent {
         name: Name,
         position: Position,
     },
+    MapChanged {
+        map: TileMap,
+    },
+    TilesChanged {
+        tiles: Vec<(Position, TileType)>,
+    },
+    GlyphChanged {
+        entity_id: EntityId,
+        glyph: Glyph,
+    },
+    TookStairs {
+        entity_id: EntityId,
+        stairs: Stairs,
+    },
+    LevelChanged,
+    FieldOfView {
+        visible_tiles: HashSet<Position>,
+        visible_entities: HashSet<EntityId>,
+        light_map: LightMap,
+    },
 }
 
-fn spawn_world(mut commands: Commands) {
-    let game_world = Game::new(
-        vec![rules::collision, rules::death, rules::compute_energy_cost],
+fn rules() -> Vec<Rule> {
+    vec![
+        rules::collision,
+        rules::forget_attack_confirmation,
+        rules::traps,
+        rules::remember_tiles,
+        rules::hazards,
+        rules::death,
+        rules::derived_stats,
+        rules::compute_energy_cost,
+    ]
+}
+
+fn action_callbacks() -> Vec<ActionCallback> {
+    vec![
+        on_created,
+        on_moved,
+        on_deleted,
+        on_map_changed,
+        on_glyph_changed,
+        on_took_stairs,
+    ]
+}
+
+fn state_callbacks() -> Vec<StateCallback> {
+    vec![update_field_of_view]
+}
+
+fn new_game_world() -> Game {
+    Game::new(
+        rules(),
         populate_action,
-        vec![on_created, on_moved, on_deleted],
+        action_callbacks(),
         vec![],
+        state_callbacks(),
+    )
+}
+
+fn game_world_from_state(state: GameState) -> Game {
+    Game::new_with_initial_state(
+        rules(),
+        populate_action,
+        action_callbacks(),
         vec![],
-    );
+        state_callbacks(),
+        state,
+    )
+}
+
+fn spawn_world(
+    mut commands: Commands,
+    mut id_generator: ResMut<EntityIdGenerator>,
+    data_asset: Res<Assets<GameData>>,
+    game_data_handle: Res<GameDataHandle>,
+) {
+    let game_data = data_asset
+        .get(&game_data_handle.0)
+        .expect("Failed to get game data");
+
+    let seed = rand::random();
+    println!("Generating world with seed {seed}");
+    let (mut game_world, player_start) = generate_level(0, seed, &mut id_generator, game_data);
+
+    if let Some(player_template) = game_data.entities.get("player") {
+        let player_id = id_generator.next();
+        println!("Player is {:?}", player_id);
+
+        game_world.enqueue_action(ActionType::CreateEntity {
+            entity_id: player_id,
+            position: player_start,
+            is_player: true,
+            is_solid: true,
+            template: player_template.clone(),
+            cost: 0,
+        });
+        game_world.process_actions();
+    }
 
     create_save_path(&mut commands, "world1").expect("Failed to build save path");
     commands.insert_resource(game_world);
+    commands.insert_resource(GameSeed(seed));
+    commands.insert_resource(LevelStore::new());
     commands.insert_resource(NextState(AppState::InGame));
 }
 
+/// Builds a brand new level, each depth having its own seed derived from the
+/// game one. Returns the world and where the player arrives on it.
+fn generate_level(
+    depth: u32,
+    seed: u64,
+    id_generator: &mut EntityIdGenerator,
+    game_data: &GameData,
+) -> (Game, Position) {
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(u64::from(depth)));
+    let generator = mapgen::random_generator(&mut rng);
+    println!("Using the {} generator for depth {depth}", generator.name());
+    let mut generated = generator.generate(MAP_WIDTH, MAP_HEIGHT, &mut rng);
+    mapgen::add_terrain_features(&mut generated, depth, &mut rng);
+    let mut prefabs: Vec<_> = game_data.prefabs.iter().collect();
+    prefabs.sort_by_key(|&(id, _)| id);
+    let prefabs: Vec<_> = prefabs.into_iter().map(|(_, prefab)| prefab).collect();
+    mapgen::prefab::place_prefabs(&mut generated, &prefabs, &mut rng);
+    let player_start = generated.player_start;
+
+    let mut game_world = new_game_world();
+    populate_level(
+        &mut game_world,
+        id_generator,
+        game_data,
+        depth,
+        generated,
+        &mut rng,
+    );
+
+    (game_world, player_start)
+}
+
+/// Puts the stairs, monsters and items in the spawn regions. The start of the
+/// map is kept free for the player.
+fn populate_level(
+    world: &mut Game,
+    id_generator: &mut EntityIdGenerator,
+    game_data: &GameData,
+    depth: u32,
+    generated: GeneratedMap,
+    rng: &mut StdRng,
+) {
+    let GeneratedMap {
+        map,
+        player_start,
+        spawn_regions,
+        forced_doors,
+        placeholders,
+    } = generated;
+
+    let mut occupied = HashSet::new();
+    occupied.insert(player_start);
+
+    place_doors(
+        world,
+        id_generator,
+        &map,
+        player_start,
+        &forced_doors,
+        &mut occupied,
+        rng,
+    );
+
+    world.enqueue_action(ActionType::CreateMap {
+        entity_id: id_generator.next(),
+        map,
+        cost: 0,
+    });
+    world.enqueue_action(ActionType::CreateFactionTable {
+        entity_id: id_generator.next(),
+        table: FactionTable::new(game_data.relations.iter().cloned()),
+        cost: 0,
+    });
+    world.process_actions();
+
+    if depth > 0 {
+        world.enqueue_action(ActionType::CreateStairs {
+            entity_id: id_generator.next(),
+            position: player_start,
+            stairs: Stairs::Up,
+            cost: 0,
+        });
+    }
+
+    if let Some(&position) = spawn_regions.last().and_then(|region| region.choose(rng)) {
+        occupied.insert(position);
+        world.enqueue_action(ActionType::CreateStairs {
+            entity_id: id_generator.next(),
+            position,
+            stairs: Stairs::Down,
+            cost: 0,
+        });
+    }
+
+    for (position, template_id) in placeholders {
+        if occupied.insert(position) {
+            spawn_template(world, id_generator, game_data, &template_id, position);
+        }
+    }
+
+    for region in spawn_regions.iter() {
+        let spawn_counts = [
+            (
+                SpawnKind::Monster,
+                rng.gen_range(0..=MAX_MONSTER_GROUPS_PER_REGION),
+            ),
+            (SpawnKind::Item, rng.gen_range(0..=MAX_ITEMS_PER_REGION)),
+            (SpawnKind::Trap, usize::from(rng.gen_bool(TRAP_CHANCE))),
+        ];
+
+        for (kind, count) in spawn_counts {
+            for _ in 0..count {
+                if let (Some(rolled), Some(&center)) = (
+                    spawns::roll(&game_data.spawns, kind, depth, rng),
+                    region.choose(rng),
+                ) {
+                    spawn_group(
+                        world,
+                        id_generator,
+                        game_data,
+                        rolled,
+                        center,
+                        region,
+                        &mut occupied,
+                    );
+                }
+            }
+        }
+    }
+
+    world.process_actions();
+
+    // Some monsters start the level asleep
+    let mut monsters: Vec<_> = world.state.aibrain.keys().copied().collect();
+    monsters.sort_by_key(|entity_id| entity_id.0);
+    for entity_id in monsters {
+        if let Some(&awareness) = world.state.get_awareness(entity_id) {
+            if rng.gen_bool(SLEEPING_CHANCE) {
+                world.enqueue_action(ActionType::UpdateAwareness {
+                    entity_id,
+                    awareness: Awareness {
+                        alertness: Alertness::Sleeping,
+                        ..awareness
+                    },
+                });
+            }
+        }
+    }
+    world.process_actions();
+}
+
+/// Puts a group rolled from the spawn tables together in the region, around
+/// `center`.
+fn spawn_group(
+    world: &mut Game,
+    id_generator: &mut EntityIdGenerator,
+    game_data: &GameData,
+    (entry, size): (&SpawnEntry, u32),
+    center: Position,
+    region: &[Position],
+    occupied: &mut HashSet<Position>,
+) {
+    let mut spots: Vec<_> = region
+        .iter()
+        .copied()
+        .filter(|position| !occupied.contains(position))
+        .collect();
+    spots.sort_by_key(|position| {
+        (position.x - center.x)
+            .abs()
+            .max((position.y - center.y).abs())
+    });
+
+    let mut members = vec![];
+    for &position in spots.iter().take(size as usize) {
+        occupied.insert(position);
+        members.extend(spawn_template(
+            world,
+            id_generator,
+            game_data,
+            &entry.template_id,
+            position,
+        ));
+    }
+
+    // Monsters spawned together hunt as a pack, led by the first one
+    if entry.kind == SpawnKind::Monster && members.len() > 1 {
+        let leader_id = members[0];
+        for entity_id in members {
+            world.enqueue_action(ActionType::JoinPack {
+                entity_id,
+                leader_id,
+            });
+        }
+    }
+}
+
+/// Creates a monster, an item or a trap from its template id. Returns the id
+/// of the new entity.
+fn spawn_template(
+    world: &mut Game,
+    id_generator: &mut EntityIdGenerator,
+    game_data: &GameData,
+    template_id: &str,
+    position: Position,
+) -> Option<EntityId> {
+    let entity_id = id_generator.next();
+    if let Some(template) = game_data.entities.get(template_id) {
+        world.enqueue_action(ActionType::CreateEntity {
+            entity_id,
+            position,
+            is_player: false,
+            is_solid: true,
+            template: template.clone(),
+            cost: 0,
+        });
+    } else if let Some(template) = game_data.items.get(template_id) {
+        world.enqueue_action(ActionType::CreateItem {
+            entity_id,
+            position,
+            glyph: template.glyph,
+            name: template.name.clone(),
+            healing: template.healing,
+            light: template.light,
+            cost: 0,
+        });
+    } else if let Some(template) = game_data.traps.get(template_id) {
+        world.enqueue_action(ActionType::CreateTrap {
+            entity_id,
+            position,
+            template: template.clone(),
+            cost: 0,
+        });
+    } else {
+        println!("Unknown template {template_id}");
+        return None;
+    }
+    Some(entity_id)
+}
+
+/// Closes some of the door frames of the map, and all of the `forced_doors`.
+/// The key of a locked door is dropped next to the start of the level so that
+/// it is always reachable.
+fn place_doors(
+    world: &mut Game,
+    id_generator: &mut EntityIdGenerator,
+    map: &TileMap,
+    player_start: Position,
+    forced_doors: &[Position],
+    occupied: &mut HashSet<Position>,
+    rng: &mut StdRng,
+) {
+    let door_frames: Vec<_> = map
+        .tiles()
+        .filter(|&(_, tile)| tile == TileType::DoorFrame)
+        .map(|(position, _)| position)
+        .collect();
+    let key_spots: Vec<_> = map
+        .tiles()
+        .filter(|&(position, tile)| {
+            tile == TileType::Floor
+                && (position.x - player_start.x).abs() <= 2
+                && (position.y - player_start.y).abs() <= 2
+        })
+        .map(|(position, _)| position)
+        .collect();
+
+    for position in door_frames {
+        let forced = forced_doors.contains(&position);
+        if !(forced || rng.gen_bool(DOOR_CHANCE)) || !occupied.insert(position) {
+            continue;
+        }
+
+        let door_id = id_generator.next();
+        let mut door_state = DoorState::Closed;
+
+        if rng.gen_bool(LOCKED_DOOR_CHANCE) {
+            if let Some(&key_position) = key_spots.choose(rng) {
+                if occupied.insert(key_position) {
+                    door_state = DoorState::Locked;
+                    world.enqueue_action(ActionType::CreateKey {
+                        entity_id: id_generator.next(),
+                        position: key_position,
+                        key: Key(door_id.0),
+                        cost: 0,
+                    });
+                }
+            }
+        }
+
+        world.enqueue_action(ActionType::CreateDoor {
+            entity_id: door_id,
+            position,
+            door: Door {
+                state: door_state,
+                key: Some(door_id.0),
+            },
+            cost: 0,
+        });
+    }
+}
+
 fn load_world(mut commands: Commands) {
     let save_path = create_save_path(&mut commands, "world1").expect("Failed to build save path");
-    let game_state = load_saved_game(save_path).expect("Failed to load saved game");
+    let SaveData {
+        seed,
+        next_entity_id,
+        current_depth,
+        current_level,
+        levels,
+    } = load_saved_game(save_path).expect("Failed to load saved game");
 
-    let game_world = Game::new_with_initial_state(
-        vec![rules::collision, rules::death],
-        populate_action,
-        vec![on_created, on_moved, on_deleted],
-        vec![],
-        vec![update_nearby_list],
-        game_state,
-    );
+    let mut game_world = game_world_from_state(current_level);
+    queue_level_events(&mut game_world);
 
     commands.insert_resource(game_world);
+    commands.insert_resource(GameSeed(seed));
+    commands.insert_resource(EntityIdGenerator {
+        next_id: next_entity_id,
+    });
+    commands.insert_resource(LevelStore {
+        current_depth,
+        levels,
+    });
     commands.insert_resource(NextState(AppState::InGame));
 }
 
+/// Asks the renderer to drop every sprite and to draw the current level from
+/// scratch.
+fn queue_level_events(world: &mut Game) {
+    let mut events = VecDeque::new();
+    events.push_back(GameEvent::LevelChanged);
+
+    if let Some(map) = world.state.map() {
+        events.push_back(GameEvent::MapChanged { map: map.clone() });
+    }
+
+    for &PositionTreeObject { index, entity_at } in world.spatial_position.iter() {
+        if let (Some(&glyph), Some(name)) = (
+            world.state.get_glyph(entity_at),
+            world.state.get_name(entity_at),
+        ) {
+            events.push_back(GameEvent::Spawned {
+                entity_id: entity_at,
+                x: index.x,
+                y: index.y,
+                glyph,
+                name: name.clone(),
+                is_player: world.state.get_player(entity_at).is_some(),
+            });
+        }
+    }
+
+    update_field_of_view(&mut events, &world.state, &world.spatial_position);
+
+    world.events_queue.extend(events);
+}
+
 fn on_created(
     events_queue: &mut VecDeque<GameEvent>,
     action: &Action,
@@ -150,16 +626,82 @@ fn on_moved(
     }
 }
 
-fn update_nearby_list(
+fn on_map_changed(
+    events_queue: &mut VecDeque<GameEvent>,
+    action: &Action,
+    state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) {
+    for (&id, map) in action.get_updated_tilemap() {
+        // Revealed tiles are followed by the renderer through the field of view
+        match state.get_tilemap(id) {
+            None => events_queue.push_back(GameEvent::MapChanged { map: map.clone() }),
+            Some(old_map) => {
+                let tiles = old_map.changed_tiles(map);
+                if !tiles.is_empty() {
+                    events_queue.push_back(GameEvent::TilesChanged { tiles });
+                }
+            }
+        }
+    }
+}
+
+fn on_glyph_changed(
+    events_queue: &mut VecDeque<GameEvent>,
+    action: &Action,
+    state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) {
+    for (&id, &glyph) in action.get_updated_glyph() {
+        // New entities get their glyph with the Spawned event
+        if state.get_glyph(id).is_some() {
+            events_queue.push_back(GameEvent::GlyphChanged {
+                entity_id: id,
+                glyph,
+            });
+        }
+    }
+}
+
+fn on_took_stairs(
+    events_queue: &mut VecDeque<GameEvent>,
+    action: &Action,
+    _state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) {
+    for (&id, &taking_stairs) in action.get_updated_takingstairs() {
+        events_queue.push_back(GameEvent::TookStairs {
+            entity_id: id,
+            stairs: taking_stairs.0,
+        });
+    }
+}
+
+/// Only what the player actually sees is reported to the renderer.
+fn update_field_of_view(
     events_queue: &mut VecDeque<GameEvent>,
     state: &GameState,
     spatial_position: &RTree<PositionTreeObject>,
 ) {
     for &player_id in state.player.keys() {
         if let Some(&player_position) = state.get_position(player_id) {
-            for PositionTreeObject { index, entity_at } in
-                spatial_position.locate_within_distance(player_position, 15)
+            let light_map = lighting::compute_light_map(state, spatial_position, |light_id| {
+                lighting::light_position(state, light_id)
+            });
+            let visible_tiles = lighting::visible_in_light(
+                fov::field_of_view(state, spatial_position, player_position, fov::VIEW_RADIUS),
+                &light_map,
+                player_position,
+            );
+            let mut visible_entities = HashSet::new();
+
+            for PositionTreeObject { index, entity_at } in spatial_position
+                .locate_within_distance(player_position, fov::VIEW_RADIUS * fov::VIEW_RADIUS)
             {
+                if !visible_tiles.contains(index) || state.get_hidden(*entity_at).is_some() {
+                    continue;
+                }
+                visible_entities.insert(*entity_at);
                 if let Some(name) = state.get_name(*entity_at) {
                     events_queue.push_back(GameEvent::EntityNearby {
                         entity_id: *entity_at,
@@ -168,11 +710,17 @@ fn update_nearby_list(
                     });
                 }
             }
+
+            events_queue.push_back(GameEvent::FieldOfView {
+                visible_tiles,
+                visible_entities,
+                light_map,
+            });
         }
     }
 }
 
-struct EntityIdGenerator {
+pub struct EntityIdGenerator {
     next_id: u64,
 }
 
@@ -181,81 +729,13 @@ impl EntityIdGenerator {
         EntityIdGenerator { next_id: 0 }
     }
 
-    fn next(&mut self) -> EntityId {
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    pub fn next(&mut self) -> EntityId {
         let next = self.next_id;
         self.next_id += 1;
         EntityId(next)
     }
 }
-
-// DEBUG //////////////////////////////////////////////////////////////////////////////////////////
-fn spawn_player(
-    mut world: ResMut<Game>,
-    mut id_generator: ResMut<EntityIdGenerator>,
-    data_asset: Res<Assets<GameData>>,
-    game_data_handle: Res<GameDataHandle>,
-) {
-    let game_data = data_asset
-        .get(&game_data_handle.0)
-        .expect("Failed to get game data");
-    if let Some(player_template) = game_data.entities.get("player") {
-        let template = player_template.clone();
-        let player_id = id_generator.next();
-        println!("Player is {:?}", player_id);
-
-        world.enqueue_action(ActionType::CreateEntity {
-            entity_id: player_id,
-            position: Position { x: 0, y: 0 },
-            is_player: true,
-            is_solid: true,
-            template,
-            cost: 0,
-        });
-        world.process_actions();
-    }
-}
-
-fn spawn_orcs(
-    mut world: ResMut<Game>,
-    mut id_generator: ResMut<EntityIdGenerator>,
-    data_asset: Res<Assets<GameData>>,
-    game_data_handle: Res<GameDataHandle>,
-) {
-    let game_data = data_asset
-        .get(&game_data_handle.0)
-        .expect("Failed to get game data");
-    if let Some(orc_template) = game_data.entities.get("orc") {
-        for i in 0..=1 {
-            let template = orc_template.clone();
-            let entity_id = id_generator.next();
-            world.enqueue_action(ActionType::CreateEntity {
-                entity_id,
-                position: Position { x: 5, y: i },
-                is_player: false,
-                is_solid: true,
-                template,
-                cost: 0,
-            });
-        }
-        world.process_actions();
-    }
-}
-
-fn spawn_potion(
-    mut world: ResMut<Game>,
-    mut id_generator: ResMut<EntityIdGenerator>,
-    _data_asset: Res<Assets<GameData>>,
-    _game_data_handle: Res<GameDataHandle>,
-) {
-    world.enqueue_action(ActionType::CreateItem {
-        entity_id: id_generator.next(),
-        position: Position { x: 2, y: 2 },
-        glyph: Glyph {
-            character: '!',
-            color: Color::GREEN,
-        },
-        name: Name("Health Potion".to_owned()),
-        cost: 0,
-    });
-    world.process_actions();
-}
@@ -0,0 +1,111 @@
+use super::{
+    components::{EntityId, GameState, Morale, Relation},
+    perception::distance,
+};
+
+pub const DEFAULT_FLEE_THRESHOLD: i64 = 25;
+pub const DEFAULT_COURAGE: i64 = 50;
+/// Morale lost by the monsters seeing an ally die.
+const ALLY_DEATH_LOSS: i64 = 20;
+/// Morale lost by a pack when its leader dies, on top of `ALLY_DEATH_LOSS`.
+/// The pack breaks and flees until its members got their courage back.
+const LEADER_DEATH_LOSS: i64 = 40;
+/// Monsters this close to a dying ally notice it.
+const ALLY_DEATH_RADIUS: i64 = 8;
+/// Morale and health regained every turn spent out of sight of enemies.
+const MORALE_RECOVERY: i64 = 2;
+pub const HEALTH_RECOVERY: i64 = 1;
+/// Cornered monsters hit this much harder, in percent.
+pub const CORNERED_DAMAGE: i64 = 150;
+
+impl Morale {
+    pub fn new(flee_threshold: i64, courage: i64) -> Self {
+        Morale {
+            flee_threshold,
+            courage,
+            morale: courage,
+            fleeing: false,
+            cornered: false,
+        }
+    }
+
+    fn shaken(self, loss: i64) -> Self {
+        Morale {
+            morale: self.morale - loss,
+            ..self
+        }
+    }
+
+    fn broken(self, loss: i64) -> Self {
+        Morale {
+            fleeing: true,
+            ..self.shaken(loss)
+        }
+    }
+}
+
+impl Default for Morale {
+    fn default() -> Self {
+        Morale::new(DEFAULT_FLEE_THRESHOLD, DEFAULT_COURAGE)
+    }
+}
+
+fn health_percent(state: &GameState, entity_id: EntityId) -> i64 {
+    match (state.get_health(entity_id), state.get_maxhealth(entity_id)) {
+        (Some(health), Some(max_health)) if max_health.0 > 0 => health.0 * 100 / max_health.0,
+        _ => 100,
+    }
+}
+
+/// Morale of a monster after a turn, depending on whether it has an enemy in
+/// sight. Monsters run away when hurt or shaken, and come back once they have
+/// recovered out of sight.
+pub fn update(state: &GameState, entity_id: EntityId, sees_enemy: bool) -> Option<Morale> {
+    let mut morale = *state.get_morale(entity_id)?;
+    let health = health_percent(state, entity_id);
+
+    if !sees_enemy {
+        morale.morale = (morale.morale + MORALE_RECOVERY).min(morale.courage);
+        morale.cornered = false;
+    }
+    morale.fleeing = if morale.fleeing {
+        morale.morale < morale.courage || health < morale.flee_threshold
+    } else {
+        morale.morale <= 0 || health < morale.flee_threshold
+    };
+    morale.cornered &= morale.fleeing;
+
+    Some(morale)
+}
+
+/// New morale of the monsters that see `entity_id` die: its allies around,
+/// and the whole pack it led, which breaks.
+pub fn shaken_by_death(state: &GameState, entity_id: EntityId) -> Vec<(EntityId, Morale)> {
+    let position = match state.get_position(entity_id) {
+        Some(&position) => position,
+        None => return vec![],
+    };
+
+    state
+        .morale
+        .iter()
+        .filter(|&(&other_id, _)| other_id != entity_id)
+        .filter_map(|(&other_id, &morale)| {
+            let led = state
+                .get_pack(other_id)
+                .map_or(false, |pack| pack.leader == entity_id);
+            let witnessed = state
+                .get_position(other_id)
+                .map_or(false, |&other_position| {
+                    distance(position, other_position) <= ALLY_DEATH_RADIUS
+                })
+                && state.relation(other_id, entity_id) == Relation::Allied;
+
+            match (witnessed, led) {
+                (_, true) => Some((other_id, morale.broken(ALLY_DEATH_LOSS + LEADER_DEATH_LOSS))),
+                (true, false) => Some((other_id, morale.shaken(ALLY_DEATH_LOSS))),
+                (false, false) => None,
+            }
+        })
+        .collect()
+}
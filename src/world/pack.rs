@@ -0,0 +1,63 @@
+use super::{
+    actions::ActionType,
+    components::{Alertness, EntityId, GameState, Position},
+    perception::distance,
+};
+
+/// Pack members this close to their leader are together.
+pub const GATHER_RADIUS: i64 = 3;
+/// Members farther than this from their leader are not waited for.
+const STRAGGLER_RADIUS: i64 = 12;
+
+/// Members of the pack led by `leader_id`, the leader included.
+pub fn members(state: &GameState, leader_id: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    state
+        .pack
+        .iter()
+        .filter(move |(_, pack)| pack.leader == leader_id)
+        .map(|(&member_id, _)| member_id)
+}
+
+/// Whether the pack is ready to fight the player at `target`: every awake
+/// member is close to the leader, or the fight already started.
+pub fn is_gathered(state: &GameState, leader_id: EntityId, target: Position) -> bool {
+    let leader_position = match state.get_position(leader_id) {
+        Some(&position) => position,
+        None => return true,
+    };
+    let positions: Vec<_> = members(state, leader_id)
+        .filter(|&member_id| {
+            state
+                .get_awareness(member_id)
+                .map_or(true, |awareness| awareness.alertness != Alertness::Sleeping)
+        })
+        .filter_map(|member_id| state.get_position(member_id).copied())
+        .filter(|&position| distance(position, leader_position) <= STRAGGLER_RADIUS)
+        .collect();
+
+    positions
+        .iter()
+        .any(|&position| distance(position, target) <= 1)
+        || positions
+            .iter()
+            .all(|&position| distance(position, leader_position) <= GATHER_RADIUS)
+}
+
+/// A member that spotted the player at `target` calls the rest of the pack.
+pub fn call_pack(state: &GameState, entity_id: EntityId, target: Position) -> Vec<ActionType> {
+    let leader_id = match state.get_pack(entity_id) {
+        Some(pack) => pack.leader,
+        None => return vec![],
+    };
+
+    members(state, leader_id)
+        .filter(|&member_id| member_id != entity_id)
+        .filter_map(|member_id| {
+            let awareness = *state.get_awareness(member_id)?;
+            (!awareness.is_searching()).then(|| ActionType::UpdateAwareness {
+                entity_id: member_id,
+                awareness: awareness.alerted_to(target),
+            })
+        })
+        .collect()
+}
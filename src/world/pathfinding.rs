@@ -0,0 +1,498 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use rstar::RTree;
+
+use super::{
+    actions::carries_key,
+    components::{DoorState, EntityId, GameState, Position, PositionTreeObject},
+};
+
+/// Cost of the cheapest tile, keeps the A* heuristic admissible.
+const MIN_TILE_COST: u32 = 75;
+const DEFAULT_MAX_COST: u32 = 100 * 200;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+impl Connectivity {
+    pub fn directions(self) -> &'static [(i64, i64)] {
+        match self {
+            Connectivity::Four => &[(0, 1), (1, 0), (0, -1), (-1, 0)],
+            Connectivity::Eight => &[
+                (0, 1),
+                (1, 0),
+                (0, -1),
+                (-1, 0),
+                (1, 1),
+                (1, -1),
+                (-1, -1),
+                (-1, 1),
+            ],
+        }
+    }
+
+    /// Lower bound of the number of moves between two positions.
+    fn distance(self, from: Position, to: Position) -> u32 {
+        let dx = (to.x - from.x).unsigned_abs() as u32;
+        let dy = (to.y - from.y).unsigned_abs() as u32;
+        match self {
+            Connectivity::Four => dx + dy,
+            Connectivity::Eight => dx.max(dy),
+        }
+    }
+}
+
+/// What a path is allowed to go through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Passability {
+    pub connectivity: Connectivity,
+    /// Solid entities are walked around, except the one standing on the goal.
+    pub avoid_solid: bool,
+    /// Closed doors can be opened on the way.
+    pub open_doors: bool,
+    /// Entity whose keys open the matching locked doors on the way.
+    pub key_carrier: Option<EntityId>,
+    pub avoid_hazards: bool,
+    /// Highest cost of a path, so that searches stay cheap.
+    pub max_cost: u32,
+}
+
+impl Default for Passability {
+    fn default() -> Self {
+        Passability {
+            connectivity: Connectivity::Eight,
+            avoid_solid: true,
+            open_doors: true,
+            key_carrier: None,
+            avoid_hazards: true,
+            max_cost: DEFAULT_MAX_COST,
+        }
+    }
+}
+
+impl Passability {
+    /// Default passability of an entity, which can use the keys it carries.
+    pub fn for_entity(entity_id: EntityId) -> Self {
+        Passability {
+            key_carrier: Some(entity_id),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NoPath {
+    NoMap,
+    GoalOutOfBounds,
+    /// The goal itself can't be walked on, like a wall.
+    GoalBlocked,
+    Unreachable,
+    /// A path may exist but costs more than `Passability::max_cost`.
+    TooExpensive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    /// Positions to walk through, the start excluded and the goal included.
+    pub steps: Vec<Position>,
+    /// Sum of the move costs of the tiles, in percent of a plain move.
+    pub cost: u32,
+}
+
+impl Path {
+    /// Move to do from `from` to follow the path.
+    pub fn next_step(&self, from: Position) -> Option<(i64, i64)> {
+        self.steps
+            .first()
+            .map(|step| (step.x - from.x, step.y - from.y))
+    }
+}
+
+/// Cost of stepping on `position`, `None` when it can't be walked on. The
+/// `goal` of a search can be stepped on even if it is dangerous or taken.
+pub fn step_cost(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    passability: &Passability,
+    position: Position,
+    goal: Option<Position>,
+) -> Option<u32> {
+    let is_goal = goal == Some(position);
+
+    // Outside of the map is a wall
+    let tile = state.map()?.get(position);
+    if tile.is_blocking() {
+        return None;
+    }
+    if passability.avoid_hazards && tile.hazard().is_some() && !is_goal {
+        return None;
+    }
+
+    for &PositionTreeObject { entity_at, .. } in spatial_position.locate_all_at_point(&position) {
+        if let Some(door) = state.get_door(entity_at) {
+            let passable = match door.state {
+                DoorState::Open => true,
+                DoorState::Closed => passability.open_doors,
+                DoorState::Locked => match (passability.key_carrier, door.key) {
+                    (Some(carrier_id), Some(key)) => carries_key(state, carrier_id, key),
+                    _ => false,
+                },
+            };
+            if !passable {
+                return None;
+            }
+        } else if passability.avoid_solid && !is_goal && state.get_solid(entity_at).is_some() {
+            return None;
+        }
+    }
+
+    Some(tile.move_cost())
+}
+
+/// A* search over the map of the level, from `start` to `goal`.
+pub fn find_path(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    start: Position,
+    goal: Position,
+    passability: &Passability,
+) -> Result<Path, NoPath> {
+    let map = state.map().ok_or(NoPath::NoMap)?;
+    if !map.in_bounds(goal) {
+        return Err(NoPath::GoalOutOfBounds);
+    }
+    if map.is_blocking(goal) {
+        return Err(NoPath::GoalBlocked);
+    }
+    if start == goal {
+        return Ok(Path {
+            steps: vec![],
+            cost: 0,
+        });
+    }
+
+    let heuristic =
+        |position: Position| passability.connectivity.distance(position, goal) * MIN_TILE_COST;
+
+    let mut open = BinaryHeap::new();
+    let mut costs: HashMap<Position, u32> = HashMap::new();
+    let mut came_from: HashMap<Position, Position> = HashMap::new();
+    let mut too_expensive = false;
+
+    costs.insert(start, 0);
+    open.push(Reverse((heuristic(start), start.x, start.y)));
+
+    while let Some(Reverse((_, x, y))) = open.pop() {
+        let current = Position { x, y };
+        if current == goal {
+            return Ok(Path {
+                steps: rebuild_path(&came_from, start, goal),
+                cost: costs[&goal],
+            });
+        }
+
+        let current_cost = costs[&current];
+        for &(dx, dy) in passability.connectivity.directions() {
+            let next = Position {
+                x: current.x + dx,
+                y: current.y + dy,
+            };
+            let step = match step_cost(state, spatial_position, passability, next, Some(goal)) {
+                Some(step) => step,
+                None => continue,
+            };
+
+            let cost = current_cost + step;
+            if cost > passability.max_cost {
+                too_expensive = true;
+                continue;
+            }
+            if costs.get(&next).map_or(true, |&known| cost < known) {
+                costs.insert(next, cost);
+                came_from.insert(next, current);
+                open.push(Reverse((cost + heuristic(next), next.x, next.y)));
+            }
+        }
+    }
+
+    if too_expensive {
+        Err(NoPath::TooExpensive)
+    } else {
+        Err(NoPath::Unreachable)
+    }
+}
+
+fn rebuild_path(
+    came_from: &HashMap<Position, Position>,
+    start: Position,
+    goal: Position,
+) -> Vec<Position> {
+    let mut steps = vec![goal];
+    let mut current = goal;
+    while let Some(&previous) = came_from.get(&current) {
+        if previous == start {
+            break;
+        }
+        steps.push(previous);
+        current = previous;
+    }
+    steps.reverse();
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        components::{CarriedBy, Door, Key, Solid},
+        map::{TileMap, TileType},
+        new_game_world,
+    };
+
+    const WALKER: EntityId = EntityId(1);
+
+    /// Level drawn with `#` for walls, `+` for door frames, `=` for roads,
+    /// `:` for rubble, `~` for shallow water and anything else for floor.
+    fn level(rows: &[&str]) -> GameState {
+        let mut map = TileMap::new(rows[0].len() as i64, rows.len() as i64, TileType::Floor);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, character) in row.chars().enumerate() {
+                let tile = match character {
+                    '#' => TileType::Wall,
+                    '+' => TileType::DoorFrame,
+                    '=' => TileType::Road,
+                    ':' => TileType::Rubble,
+                    '~' => TileType::ShallowWater,
+                    _ => TileType::Floor,
+                };
+                map.set(
+                    Position {
+                        x: x as i64,
+                        y: y as i64,
+                    },
+                    tile,
+                );
+            }
+        }
+
+        let mut state = new_game_world().state;
+        state.tilemap.insert(EntityId(0), map);
+        state
+    }
+
+    fn spatial_position(state: &GameState) -> RTree<PositionTreeObject> {
+        RTree::bulk_load(
+            state
+                .position
+                .iter()
+                .map(|(&entity_at, &index)| PositionTreeObject { index, entity_at })
+                .collect(),
+        )
+    }
+
+    fn path(
+        state: &GameState,
+        start: (i64, i64),
+        goal: (i64, i64),
+        passability: &Passability,
+    ) -> Result<Path, NoPath> {
+        find_path(
+            state,
+            &spatial_position(state),
+            Position {
+                x: start.0,
+                y: start.1,
+            },
+            Position {
+                x: goal.0,
+                y: goal.1,
+            },
+            passability,
+        )
+    }
+
+    fn open_room() -> GameState {
+        level(&[
+            "#######", //
+            "#.....#", //
+            "#.....#", //
+            "#.....#", //
+            "#.....#", //
+            "#.....#", //
+            "#######",
+        ])
+    }
+
+    /// Two rooms joined by a door frame.
+    fn rooms_with_door(door_state: DoorState) -> GameState {
+        let mut state = level(&[
+            "#######", //
+            "#..#..#", //
+            "#..+..#", //
+            "#..#..#", //
+            "#######",
+        ]);
+        let door_id = EntityId(2);
+        state.position.insert(door_id, Position { x: 3, y: 2 });
+        state.door.insert(
+            door_id,
+            Door {
+                state: door_state,
+                key: Some(7),
+            },
+        );
+        state
+    }
+
+    #[test]
+    fn diagonal_moves_shorten_paths() {
+        let state = open_room();
+
+        let eight = path(&state, (1, 1), (5, 5), &Passability::default()).unwrap();
+        assert_eq!(eight.steps.len(), 4);
+        assert_eq!(eight.cost, 400);
+
+        let four = Passability {
+            connectivity: Connectivity::Four,
+            ..Default::default()
+        };
+        let four = path(&state, (1, 1), (5, 5), &four).unwrap();
+        assert_eq!(four.steps.len(), 8);
+        assert_eq!(four.cost, 800);
+    }
+
+    #[test]
+    fn reports_why_there_is_no_path() {
+        let state = level(&[
+            "#######", //
+            "#..#..#", //
+            "#..#..#", //
+            "#######",
+        ]);
+        let passability = Passability::default();
+
+        assert_eq!(
+            path(&state, (1, 1), (9, 1), &passability),
+            Err(NoPath::GoalOutOfBounds)
+        );
+        assert_eq!(
+            path(&state, (1, 1), (3, 1), &passability),
+            Err(NoPath::GoalBlocked)
+        );
+        assert_eq!(
+            path(&state, (1, 1), (5, 1), &passability),
+            Err(NoPath::Unreachable)
+        );
+    }
+
+    #[test]
+    fn walks_around_solid_entities_but_onto_the_goal() {
+        let mut state = open_room();
+        let blocker = Position { x: 3, y: 3 };
+        state.position.insert(EntityId(2), blocker);
+        state.solid.insert(EntityId(2), Solid);
+
+        let around = path(&state, (1, 3), (5, 3), &Passability::default()).unwrap();
+        assert_eq!(around.steps.len(), 4);
+        assert!(!around.steps.contains(&blocker));
+
+        let onto = path(&state, (1, 3), (3, 3), &Passability::default()).unwrap();
+        assert_eq!(onto.steps.last(), Some(&blocker));
+    }
+
+    #[test]
+    fn solid_entities_block_corridors_unless_ignored() {
+        let mut state = level(&[
+            "#######", //
+            "#.....#", //
+            "#######",
+        ]);
+        state.position.insert(EntityId(2), Position { x: 3, y: 1 });
+        state.solid.insert(EntityId(2), Solid);
+
+        assert_eq!(
+            path(&state, (1, 1), (5, 1), &Passability::default()),
+            Err(NoPath::Unreachable)
+        );
+
+        let through = Passability {
+            avoid_solid: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            path(&state, (1, 1), (5, 1), &through).unwrap().steps.len(),
+            4
+        );
+    }
+
+    #[test]
+    fn closed_doors_are_opened_on_the_way_if_allowed() {
+        let state = rooms_with_door(DoorState::Closed);
+
+        assert!(path(&state, (1, 2), (5, 2), &Passability::default()).is_ok());
+
+        let closed = Passability {
+            open_doors: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            path(&state, (1, 2), (5, 2), &closed),
+            Err(NoPath::Unreachable)
+        );
+    }
+
+    #[test]
+    fn locked_doors_need_the_key_of_the_walker() {
+        let mut state = rooms_with_door(DoorState::Locked);
+
+        assert_eq!(
+            path(&state, (1, 2), (5, 2), &Passability::default()),
+            Err(NoPath::Unreachable)
+        );
+        assert_eq!(
+            path(&state, (1, 2), (5, 2), &Passability::for_entity(WALKER)),
+            Err(NoPath::Unreachable)
+        );
+
+        let key_id = EntityId(3);
+        state.key.insert(key_id, Key(7));
+        state.carriedby.insert(key_id, CarriedBy(WALKER));
+
+        let unlocked = path(&state, (1, 2), (5, 2), &Passability::for_entity(WALKER)).unwrap();
+        assert!(unlocked.steps.contains(&Position { x: 3, y: 2 }));
+
+        let stranger = Passability::for_entity(EntityId(4));
+        assert_eq!(
+            path(&state, (1, 2), (5, 2), &stranger),
+            Err(NoPath::Unreachable)
+        );
+    }
+
+    #[test]
+    fn prefers_roads_to_rubble_and_water() {
+        let state = level(&[
+            "#######", //
+            "#.:~:.#", //
+            "#=====#", //
+            "#######",
+        ]);
+
+        let road = path(&state, (1, 1), (5, 1), &Passability::default()).unwrap();
+        assert_eq!(
+            road.steps,
+            vec![
+                Position { x: 2, y: 2 },
+                Position { x: 3, y: 2 },
+                Position { x: 4, y: 2 },
+                Position { x: 5, y: 1 },
+            ]
+        );
+        assert_eq!(road.cost, 325);
+    }
+}
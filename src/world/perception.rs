@@ -0,0 +1,137 @@
+use rstar::RTree;
+
+use super::{
+    allies,
+    components::{
+        Alertness, Awareness, EntityId, GameState, Perception, Position, PositionTreeObject,
+        Relation,
+    },
+    fov,
+};
+
+/// Turns spent searching around the last known position of the player.
+pub const SEARCH_TURNS: u32 = 10;
+pub const DEFAULT_PERCEPTION: Perception = Perception {
+    sight: 8,
+    hearing: 4,
+};
+
+impl Default for Awareness {
+    fn default() -> Self {
+        Awareness {
+            alertness: Alertness::Idle,
+            last_known_position: None,
+            search_turns: 0,
+        }
+    }
+}
+
+impl Awareness {
+    /// Awareness of a monster that noticed something at `position`, hunting
+    /// monsters keep on hunting.
+    pub fn alerted_to(self, position: Position) -> Self {
+        Awareness {
+            alertness: match self.alertness {
+                Alertness::Hunting => Alertness::Hunting,
+                _ => Alertness::Alert,
+            },
+            last_known_position: Some(position),
+            search_turns: SEARCH_TURNS,
+        }
+    }
+
+    /// Alert or hunting monsters go to where they last noticed the player,
+    /// then search around it for a while.
+    pub fn is_searching(self) -> bool {
+        matches!(self.alertness, Alertness::Alert | Alertness::Hunting)
+    }
+}
+
+/// Chebyshev distance, the number of moves between two positions.
+pub fn distance(from: Position, to: Position) -> i64 {
+    (to.x - from.x).abs().max((to.y - from.y).abs())
+}
+
+pub fn can_see(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    from: Position,
+    to: Position,
+    sight: i64,
+) -> bool {
+    distance(from, to) <= sight
+        && fov::has_line_of_sight(from, to, |between| {
+            fov::blocks_sight(state, spatial_position, between)
+        })
+}
+
+/// Looks for enemies and listens for the player around a monster. Returns its
+/// new awareness, and the closest enemy it can see, be it the player or one of
+/// their allies.
+pub fn perceive(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    entity_id: EntityId,
+) -> (Awareness, Option<(EntityId, Position)>) {
+    let awareness = state.get_awareness(entity_id).copied().unwrap_or_default();
+    let perception = state
+        .get_perception(entity_id)
+        .copied()
+        .unwrap_or(DEFAULT_PERCEPTION);
+
+    let position = match state.get_position(entity_id) {
+        Some(&position) => position,
+        None => return (awareness, None),
+    };
+    let asleep = awareness.alertness == Alertness::Sleeping;
+    if !asleep {
+        if let Some((enemy_id, enemy_position)) =
+            allies::nearest_enemy(state, spatial_position, entity_id, perception.sight)
+        {
+            let awareness = Awareness {
+                alertness: Alertness::Hunting,
+                last_known_position: Some(enemy_position),
+                search_turns: SEARCH_TURNS,
+            };
+            return (awareness, Some((enemy_id, enemy_position)));
+        }
+    }
+
+    // Only the players enemies care about where they are
+    let player = state.player.keys().next().and_then(|&player_id| {
+        state
+            .get_position(player_id)
+            .copied()
+            .filter(|_| state.relation(entity_id, player_id) == Relation::Hostile)
+    });
+
+    if let Some(player_position) = player {
+        let hearing = if asleep {
+            perception.hearing / 2
+        } else {
+            perception.hearing
+        };
+        if distance(position, player_position) <= hearing {
+            return (awareness.alerted_to(player_position), None);
+        }
+    }
+
+    if !awareness.is_searching() {
+        return (awareness, None);
+    }
+
+    let awareness = match awareness.last_known_position {
+        // Reached the place, time to look around
+        Some(last_known_position) if last_known_position == position => Awareness {
+            last_known_position: None,
+            ..awareness
+        },
+        Some(_) => awareness,
+        None if awareness.search_turns > 1 => Awareness {
+            search_turns: awareness.search_turns - 1,
+            ..awareness
+        },
+        None => Awareness::default(),
+    };
+    (awareness, None)
+}
@@ -1,6 +1,6 @@
 use rstar::RTree;
 
-use super::{actions::*, components::*};
+use super::{actions::*, components::*, fov, lighting};
 
 pub fn collision(
     action: &Action,
@@ -12,6 +12,18 @@ pub fn collision(
     let future_state = FutureState { action, state };
 
     for (&moved_id, &new_position) in action.get_updated_position() {
+        if let Some(map) = state.map() {
+            if map.is_blocking(new_position) {
+                if let Some(name) = state.get_name(moved_id) {
+                    println!(
+                        "{name} {moved_id} bumped on {:?} at pos: {new_position:?}",
+                        map.get(new_position)
+                    );
+                }
+                return (ActionStatus::Reject, RuleStatus::StopChecking, reactions);
+            }
+        }
+
         for &PositionTreeObject { entity_at, .. } in
             spatial_position.locate_all_at_point(&new_position)
         {
@@ -19,15 +31,54 @@ pub fn collision(
                 continue;
             }
 
+            if let Some(door) = future_state.get_door(entity_at) {
+                match door.state {
+                    DoorState::Open => {}
+                    DoorState::Closed => {
+                        reactions.push(ActionType::OpenDoor {
+                            entity_id: moved_id,
+                            door_id: entity_at,
+                            cost: 100,
+                        });
+                        return (ActionStatus::Reject, RuleStatus::StopChecking, reactions);
+                    }
+                    DoorState::Locked => {
+                        println!("The door at pos: {new_position:?} is locked");
+                        return (ActionStatus::Reject, RuleStatus::StopChecking, reactions);
+                    }
+                }
+            }
+
+            // Entities swapping places get out of each other's way
+            if future_state.get_position(entity_at) != Some(&new_position) {
+                continue;
+            }
+
             if future_state.get_solid(entity_at).is_some() {
                 if future_state.get_health(entity_at).is_some() {
-                    reactions.push(ActionType::DamageEntity {
-                        attacker_id: moved_id,
-                        target_id: entity_at,
-                        cost: 0,
-                    });
-                    if let Some(name) = state.get_name(entity_at) {
-                        println!("Attacking {name} {entity_at} at pos: {new_position:?}");
+                    match state.relation(moved_id, entity_at) {
+                        // Allies make way for the player, not the other way around
+                        Relation::Allied if future_state.get_player(entity_at).is_some() => {}
+                        Relation::Allied => reactions.push(ActionType::SwapPlaces {
+                            entity_id: moved_id,
+                            other_id: entity_at,
+                            cost: 100,
+                        }),
+                        Relation::Neutral => {
+                            let confirmed = state.get_attackconfirmation(moved_id)
+                                == Some(&AttackConfirmation(entity_at));
+                            if confirmed {
+                                reactions.push(attack(state, moved_id, entity_at, new_position));
+                            } else if state.get_player(moved_id).is_some() {
+                                reactions.push(ActionType::AskAttackConfirmation {
+                                    entity_id: moved_id,
+                                    target_id: entity_at,
+                                });
+                            }
+                        }
+                        Relation::Hostile => {
+                            reactions.push(attack(state, moved_id, entity_at, new_position))
+                        }
                     }
                     return (ActionStatus::Reject, RuleStatus::StopChecking, reactions);
                 }
@@ -42,6 +93,150 @@ pub fn collision(
     (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
 }
 
+fn attack(
+    state: &GameState,
+    attacker_id: EntityId,
+    target_id: EntityId,
+    position: Position,
+) -> ActionType {
+    if let Some(name) = state.get_name(target_id) {
+        println!("Attacking {name} {target_id} at pos: {position:?}");
+    }
+    ActionType::DamageEntity {
+        attacker_id,
+        target_id,
+        cost: 0,
+    }
+}
+
+/// Doing anything else than bumping into the same neutral creature again
+/// cancels the confirmation of an attack on it.
+pub fn forget_attack_confirmation(
+    action: &Action,
+    state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
+    let mut reactions = Vec::new();
+    for (&id, &action_cost) in action.get_updated_actioncost() {
+        if action_cost.0 != 0 && state.get_attackconfirmation(id).is_some() {
+            reactions.push(ActionType::CancelAttackConfirmation { entity_id: id });
+        }
+    }
+
+    (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
+}
+
+/// Creatures stepping on a trap set it off, whether it was found or not.
+pub fn traps(
+    action: &Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
+    let mut reactions = Vec::new();
+
+    let future_state = FutureState { action, state };
+
+    for (&moved_id, &new_position) in action.get_updated_position() {
+        // Entities being created don't walk into anything
+        if state.get_position(moved_id).is_none() || future_state.get_health(moved_id).is_none() {
+            continue;
+        }
+
+        for &PositionTreeObject { entity_at, .. } in
+            spatial_position.locate_all_at_point(&new_position)
+        {
+            if let Some(trap) = state.get_trap(entity_at) {
+                reactions.push(ActionType::TriggerTrap {
+                    entity_id: moved_id,
+                    trap_id: entity_at,
+                    cost: 0,
+                });
+                if trap.damage > 0 {
+                    reactions.push(ActionType::InflictDamage {
+                        target_id: moved_id,
+                        damage: trap.damage,
+                        cost: 0,
+                    });
+                }
+            }
+        }
+    }
+
+    (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
+}
+
+/// The player remembers every tile seen from its new position.
+pub fn remember_tiles(
+    action: &Action,
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
+    let mut reactions = Vec::new();
+
+    let future_state = FutureState { action, state };
+
+    if let (Some(map_id), Some(map)) = (state.map_id(), state.map()) {
+        for (&moved_id, &new_position) in action.get_updated_position() {
+            if future_state.get_player(moved_id).is_none() {
+                continue;
+            }
+
+            // Lights carried around are where the action leaves them
+            let light_map = lighting::compute_light_map(state, spatial_position, |light_id| {
+                lighting::light_position_with(
+                    |id| future_state.get_position(id).copied(),
+                    |id| future_state.get_carriedby(id).map(|carrier| carrier.0),
+                    light_id,
+                )
+            });
+            let field_of_view =
+                fov::field_of_view(state, spatial_position, new_position, fov::VIEW_RADIUS);
+
+            let tiles: Vec<_> = lighting::visible_in_light(field_of_view, &light_map, new_position)
+                .into_iter()
+                .filter(|&position| map.in_bounds(position) && !map.is_revealed(position))
+                .collect();
+
+            if !tiles.is_empty() {
+                reactions.push(ActionType::RevealTiles { map_id, tiles });
+            }
+        }
+    }
+
+    (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
+}
+
+/// Entities ending their move on lava, acid or deep water get hurt.
+pub fn hazards(
+    action: &Action,
+    state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
+    let mut reactions = Vec::new();
+
+    let future_state = FutureState { action, state };
+
+    if let Some(map) = state.map() {
+        for (&moved_id, &new_position) in action.get_updated_position() {
+            if future_state.get_health(moved_id).is_none() {
+                continue;
+            }
+            if let Some(hazard) = map.get(new_position).hazard() {
+                if let Some(name) = future_state.get_name(moved_id) {
+                    println!("{name} {moved_id} is {hazard:?}!");
+                }
+                reactions.push(ActionType::InflictDamage {
+                    target_id: moved_id,
+                    damage: hazard.damage(),
+                    cost: 0,
+                });
+            }
+        }
+    }
+
+    (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
+}
+
 pub fn death(
     action: &Action,
     state: &GameState,
@@ -63,6 +258,40 @@ pub fn death(
     (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
 }
 
+/// Recomputes max health, accuracy, evasion, carrying capacity and speed of
+/// the creatures whose stats or initiative changed.
+pub fn derived_stats(
+    action: &Action,
+    _state: &GameState,
+    _spatial_position: &RTree<PositionTreeObject>,
+) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
+    let mut changed = Vec::new();
+    for (&id, _) in action.get_updated_might() {
+        changed.push(id);
+    }
+    for (&id, _) in action.get_updated_agility() {
+        changed.push(id);
+    }
+    for (&id, _) in action.get_updated_vitality() {
+        changed.push(id);
+    }
+    for (&id, _) in action.get_updated_will() {
+        changed.push(id);
+    }
+    for (&id, _) in action.get_updated_initiative() {
+        changed.push(id);
+    }
+    changed.sort_by_key(|id| id.0);
+    changed.dedup();
+
+    let reactions = changed
+        .into_iter()
+        .map(|entity_id| ActionType::DeriveStats { entity_id })
+        .collect();
+
+    (ActionStatus::Accept, RuleStatus::KeepChecking, reactions)
+}
+
 pub fn compute_energy_cost(
     action: &Action,
     state: &GameState,
@@ -70,11 +299,31 @@ pub fn compute_energy_cost(
 ) -> (ActionStatus, RuleStatus, Vec<ActionType>) {
     let mut reactions = Vec::new();
 
+    let future_state = FutureState { action, state };
+
     for (&id, &action_cost) in action.get_updated_actioncost() {
         if action_cost.0 != 0 {
-            if let Some(initiative) = state.get_initiative(id) {
-                let ratio = 100 / initiative.0;
-                let action_cost = action_cost.0 * ratio;
+            // Agile creatures act faster than their initiative alone allows
+            let speed = match (state.get_speed(id), state.get_initiative(id)) {
+                (Some(speed), _) => Some(speed.0),
+                (None, Some(initiative)) => Some(initiative.0),
+                (None, None) => None,
+            };
+            if let Some(speed) = speed {
+                let ratio = 100 / speed;
+                let mut action_cost = action_cost.0 * ratio;
+
+                // Moving onto a tile costs more or less depending on the terrain
+                if let (Some(map), Some(old_position), Some(&new_position)) = (
+                    state.map(),
+                    state.get_position(id),
+                    future_state.get_position(id),
+                ) {
+                    if *old_position != new_position {
+                        action_cost = action_cost * map.get(new_position).move_cost() / 100;
+                    }
+                }
+
                 reactions.push(ActionType::DecreaseEnergy {
                     entity_id: id,
                     value: action_cost,
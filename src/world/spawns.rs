@@ -0,0 +1,36 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::raw_loader::{SpawnEntry, SpawnKind};
+
+/// Picks an entry of the spawn tables for a level, each one having a chance
+/// proportional to its weight at that depth. Returns it along with the size
+/// of the group to spawn.
+pub fn roll<'a>(
+    spawns: &'a [SpawnEntry],
+    kind: SpawnKind,
+    depth: u32,
+    rng: &mut StdRng,
+) -> Option<(&'a SpawnEntry, u32)> {
+    let candidates: Vec<_> = spawns
+        .iter()
+        .filter(|entry| entry.kind == kind)
+        .map(|entry| (entry, entry.weight_at(depth)))
+        .filter(|&(_, weight)| weight > 0)
+        .collect();
+
+    let total: u32 = candidates.iter().map(|&(_, weight)| weight).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0..total);
+    for (entry, weight) in candidates {
+        if roll < weight {
+            let group_max = entry.group_max.max(entry.group_min);
+            return Some((entry, rng.gen_range(entry.group_min..=group_max)));
+        }
+        roll -= weight;
+    }
+
+    None
+}
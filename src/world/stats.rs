@@ -0,0 +1,101 @@
+use super::components::{
+    Accuracy, Agility, CarryCapacity, EntityId, Evasion, GameState, Initiative, MaxHealth, Might,
+    Speed, Vitality, Will,
+};
+
+/// Max health given by each point of vitality.
+const HEALTH_PER_VITALITY: i64 = 5;
+/// Chance to hit of an entity with no might nor agility, in percent.
+const BASE_ACCURACY: i64 = 60;
+/// Items carried by an entity with no might.
+const BASE_CARRY_CAPACITY: i64 = 5;
+/// Skill at traps of an entity with no will nor agility.
+const BASE_TRAP_SKILL: i64 = 50;
+/// Hits are never certain, nor hopeless.
+const MIN_HIT_CHANCE: i64 = 5;
+const MAX_HIT_CHANCE: i64 = 95;
+
+/// Primary stats of a creature, read from the raws.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub might: Might,
+    pub agility: Agility,
+    pub vitality: Vitality,
+    pub will: Will,
+}
+
+/// Values following from the primary stats and the initiative.
+#[derive(Debug, Clone, Copy)]
+pub struct DerivedStats {
+    pub max_health: MaxHealth,
+    pub accuracy: Accuracy,
+    pub evasion: Evasion,
+    pub carry_capacity: CarryCapacity,
+    pub speed: Speed,
+}
+
+impl Stats {
+    pub fn derive(&self, initiative: Initiative) -> DerivedStats {
+        DerivedStats {
+            max_health: MaxHealth(self.vitality.0 * HEALTH_PER_VITALITY),
+            accuracy: Accuracy(BASE_ACCURACY + 2 * self.agility.0 + self.might.0),
+            evasion: Evasion(3 * self.agility.0),
+            carry_capacity: CarryCapacity(BASE_CARRY_CAPACITY + self.might.0 / 2),
+            speed: Speed((initiative.0 as i64 + self.agility.0 / 4).max(1) as u32),
+        }
+    }
+}
+
+/// Primary stats of `entity_id`, if it has all of them.
+pub fn stats_of(state: &GameState, entity_id: EntityId) -> Option<Stats> {
+    Some(Stats {
+        might: *state.get_might(entity_id)?,
+        agility: *state.get_agility(entity_id)?,
+        vitality: *state.get_vitality(entity_id)?,
+        will: *state.get_will(entity_id)?,
+    })
+}
+
+/// Chance in percent that `attacker_id` hits `target_id`. Creatures without
+/// stats always hit.
+pub fn hit_chance(state: &GameState, attacker_id: EntityId, target_id: EntityId) -> i64 {
+    match state.get_accuracy(attacker_id) {
+        Some(accuracy) => {
+            let evasion = state.get_evasion(target_id).map_or(0, |evasion| evasion.0);
+            (accuracy.0 - evasion).clamp(MIN_HIT_CHANCE, MAX_HIT_CHANCE)
+        }
+        None => 100,
+    }
+}
+
+/// Damage of a spell cast by `caster_id`, stronger with its will.
+pub fn spell_damage(state: &GameState, caster_id: EntityId, damage: i64) -> i64 {
+    damage + state.get_will(caster_id).map_or(0, |will| will.0 / 4)
+}
+
+/// Skill at finding hidden traps, sharpened by will.
+pub fn search_skill(state: &GameState, entity_id: EntityId) -> u32 {
+    trap_skill(state.get_will(entity_id).map(|will| will.0))
+}
+
+/// Skill at disarming traps, steadied by agility.
+pub fn disarm_skill(state: &GameState, entity_id: EntityId) -> u32 {
+    trap_skill(state.get_agility(entity_id).map(|agility| agility.0))
+}
+
+/// Creatures without stats know nothing of traps.
+fn trap_skill(stat: Option<i64>) -> u32 {
+    stat.map_or(0, |stat| (BASE_TRAP_SKILL + 10 * stat).max(0) as u32)
+}
+
+/// Number of items `entity_id` can still pick up.
+pub fn carry_room(state: &GameState, entity_id: EntityId) -> i64 {
+    let carried = state
+        .carriedby
+        .values()
+        .filter(|carried_by| carried_by.0 == entity_id)
+        .count() as i64;
+    state
+        .get_carrycapacity(entity_id)
+        .map_or(i64::MAX, |capacity| capacity.0 - carried)
+}
@@ -0,0 +1,125 @@
+use rstar::RTree;
+
+use super::{
+    components::{
+        ConsiderationInput, Curve, EntityId, GameState, Position, PositionTreeObject,
+        UtilityCandidate,
+    },
+    fov::VIEW_RADIUS,
+    perception::distance,
+};
+
+/// Logs the score of every candidate of the monsters driven by utility
+/// scoring, to balance their profiles.
+#[derive(Debug, Default)]
+pub struct UtilityTrace(pub bool);
+
+/// Values of every `ConsiderationInput` for a monster.
+#[derive(Debug, Clone, Copy)]
+pub struct Inputs {
+    health: f32,
+    distance: f32,
+    adjacent: f32,
+    visible: f32,
+    in_range: f32,
+    potion: f32,
+    item_here: f32,
+}
+
+impl Inputs {
+    pub fn new(
+        state: &GameState,
+        spatial_position: &RTree<PositionTreeObject>,
+        entity_id: EntityId,
+        position: Position,
+        target: Option<Position>,
+    ) -> Self {
+        let health = match (state.get_health(entity_id), state.get_maxhealth(entity_id)) {
+            (Some(health), Some(max_health)) if max_health.0 > 0 => {
+                health.0 as f32 / max_health.0 as f32
+            }
+            _ => 1.0,
+        };
+        let target_distance = target.map(|target| distance(position, target));
+        let in_range = match (state.get_spell(entity_id), target_distance) {
+            (Some(spell), Some(target_distance)) => target_distance <= spell.range,
+            _ => false,
+        };
+
+        Inputs {
+            health,
+            distance: target_distance.map_or(1.0, |target_distance| {
+                (target_distance as f32 / VIEW_RADIUS as f32).min(1.0)
+            }),
+            adjacent: flag(target_distance.map_or(false, |target_distance| target_distance <= 1)),
+            visible: flag(target.is_some()),
+            in_range: flag(in_range),
+            potion: flag(carried_potion(state, entity_id).is_some()),
+            item_here: flag(item_at(state, spatial_position, position).is_some()),
+        }
+    }
+
+    pub fn get(&self, input: ConsiderationInput) -> f32 {
+        match input {
+            ConsiderationInput::Health => self.health,
+            ConsiderationInput::Distance => self.distance,
+            ConsiderationInput::Adjacent => self.adjacent,
+            ConsiderationInput::Visible => self.visible,
+            ConsiderationInput::InRange => self.in_range,
+            ConsiderationInput::Potion => self.potion,
+            ConsiderationInput::ItemHere => self.item_here,
+        }
+    }
+}
+
+fn flag(value: bool) -> f32 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Curve {
+    pub fn score(self, input: f32) -> f32 {
+        match self {
+            Curve::Linear { slope, offset } => (slope * input + offset).clamp(0.0, 1.0),
+            Curve::Above(threshold) => flag(input >= threshold),
+            Curve::Below(threshold) => flag(input < threshold),
+        }
+    }
+}
+
+impl UtilityCandidate {
+    pub fn score(&self, inputs: &Inputs) -> f32 {
+        self.considerations
+            .iter()
+            .map(|consideration| consideration.curve.score(inputs.get(consideration.input)))
+            .product::<f32>()
+            * self.weight
+    }
+}
+
+/// Healing item carried by `entity_id`, if any.
+pub fn carried_potion(state: &GameState, entity_id: EntityId) -> Option<EntityId> {
+    state
+        .carriedby
+        .iter()
+        .filter(|&(&item_id, carried_by)| {
+            carried_by.0 == entity_id && state.get_healing(item_id).is_some()
+        })
+        .map(|(&item_id, _)| item_id)
+        .min_by_key(|item_id| item_id.0)
+}
+
+/// Item lying on the ground at `position`, if any.
+pub fn item_at(
+    state: &GameState,
+    spatial_position: &RTree<PositionTreeObject>,
+    position: Position,
+) -> Option<EntityId> {
+    spatial_position
+        .locate_all_at_point(&position)
+        .map(|&PositionTreeObject { entity_at, .. }| entity_at)
+        .find(|&entity_at| state.get_item(entity_at).is_some())
+}